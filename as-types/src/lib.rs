@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 pub type TeeEvidenceParsedClaim = serde_json::Value;
 
@@ -7,4 +8,18 @@ pub struct SetPolicyInput {
     pub r#type: String,
     pub policy_id: String,
     pub policy: String,
+    /// If given, the policy is only evaluated against the sample data, and
+    /// the decision is returned instead of storing the policy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<PolicyDryRun>,
+}
+
+/// Sample data to evaluate a policy against.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PolicyDryRun {
+    /// The policy input, i.e. the parsed claims of a TEE evidence.
+    pub input: TeeEvidenceParsedClaim,
+    /// The reference values of the claims.
+    #[serde(default)]
+    pub reference: HashMap<String, Vec<String>>,
 }
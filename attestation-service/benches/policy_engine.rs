@@ -0,0 +1,84 @@
+// Copyright (c) 2023 by Alibaba.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Throughput of the OPA policy engine with and without the prepared queries
+//! cached, and of concurrent evaluations of a cached policy, e.g.
+//! `cargo bench -p attestation-service --bench policy_engine`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use attestation_service::policy_engine::{opa::OPA, PolicyEngine};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use serde_json::json;
+
+/// The number of evaluations run at the same time.
+const CONCURRENCY: usize = 8;
+
+fn reference() -> HashMap<String, Vec<String>> {
+    serde_json::from_value(json!({
+        "productId": ["5"],
+        "svn": ["5"],
+    }))
+    .unwrap()
+}
+
+fn input() -> String {
+    json!({
+        "productId": "5",
+        "svn": "5",
+    })
+    .to_string()
+}
+
+fn evaluate(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let work_dir = tempfile::tempdir().unwrap();
+    let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+
+    let mut group = c.benchmark_group("opa_evaluate");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("cached", |b| {
+        b.to_async(&runtime)
+            .iter(|| opa.evaluate(reference(), input(), None))
+    });
+
+    group.bench_function("uncached", |b| {
+        b.to_async(&runtime).iter_batched(
+            || opa.clear_cache().unwrap(),
+            |_| opa.evaluate(reference(), input(), None),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn evaluate_concurrent(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(CONCURRENCY)
+        .build()
+        .unwrap();
+    let work_dir = tempfile::tempdir().unwrap();
+    let opa = Arc::new(OPA::new(work_dir.path().to_path_buf()).unwrap());
+
+    let mut group = c.benchmark_group("opa_evaluate_concurrent");
+    group.throughput(Throughput::Elements(CONCURRENCY as u64));
+
+    group.bench_function("cached", |b| {
+        b.to_async(&runtime).iter(|| {
+            let tasks = (0..CONCURRENCY).map(|_| {
+                let opa = opa.clone();
+                tokio::spawn(async move { opa.evaluate(reference(), input(), None).await })
+            });
+            futures::future::join_all(tasks)
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, evaluate, evaluate_concurrent);
+criterion_main!(benches);
@@ -1,6 +1,23 @@
 use std::process::{exit, Command};
 
 fn real_main() -> Result<(), String> {
+    // The Go library is only needed by the OPA policy engine and the in-toto
+    // extractor, s.t. the AS can be built without a Go toolchain otherwise.
+    // Both link the whole library, e.g. the shared `freeGo`.
+    if std::env::var("CARGO_FEATURE_OPA_ENGINE").is_ok()
+        || std::env::var("CARGO_FEATURE_IN_TOTO").is_ok()
+    {
+        build_cgo()?;
+    }
+
+    tonic_build::compile_protos("../protos/attestation.proto").map_err(|e| format!("{e}"))?;
+
+    tonic_build::compile_protos("../protos/reference.proto").map_err(|e| format!("{e}"))?;
+
+    Ok(())
+}
+
+fn build_cgo() -> Result<(), String> {
     let out_dir = std::env::var("OUT_DIR").unwrap();
     println!("cargo:rerun-if-changed={out_dir}");
     println!("cargo:rustc-link-search=native={out_dir}");
@@ -24,10 +41,6 @@ fn real_main() -> Result<(), String> {
             .to_string());
     }
 
-    tonic_build::compile_protos("../protos/attestation.proto").map_err(|e| format!("{e}"))?;
-
-    tonic_build::compile_protos("../protos/reference.proto").map_err(|e| format!("{e}"))?;
-
     Ok(())
 }
 
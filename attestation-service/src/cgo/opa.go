@@ -1,17 +1,128 @@
 package main
 
+/*
+#include <stdlib.h>
+*/
 import "C"
 
 import (
 	"context"
 	"encoding/json"
+	"fmt"
+	"strings"
+	"sync"
+	"unsafe"
 
+	"github.com/open-policy-agent/opa/ast"
 	"github.com/open-policy-agent/opa/rego"
+	"github.com/open-policy-agent/opa/storage"
 	"github.com/open-policy-agent/opa/storage/inmem"
 )
 
+// instance is a prepared query of a policy, together with its own store
+// which the reference data of an evaluation is written to. An instance is
+// used by one evaluation at a time.
+type instance struct {
+	query rego.PreparedEvalQuery
+	store storage.Store
+}
+
+// cachedQuery is a compiled policy, with a pool of prepared instances s.t.
+// concurrent evaluations of the policy do not wait for each other's store
+// transaction.
+type cachedQuery struct {
+	digest   string
+	compiler *ast.Compiler
+	pool     sync.Pool
+}
+
+var (
+	cacheMu sync.RWMutex
+	cache   = make(map[string]*cachedQuery)
+)
+
+func prepareQuery(digest string, policy string) (*cachedQuery, error) {
+	compiler, err := ast.CompileModules(map[string]string{"policy.rego": policy})
+	if err != nil {
+		return nil, err
+	}
+
+	return &cachedQuery{digest: digest, compiler: compiler}, nil
+}
+
+// getQuery gets the compiled policy from the cache, or compiles and caches it
+// if the policy is not cached or its content has changed. An empty policyId
+// compiles the policy without caching it.
+func getQuery(policyId string, digest string, policy string) (*cachedQuery, error) {
+	if policyId == "" {
+		return prepareQuery(digest, policy)
+	}
+
+	cacheMu.RLock()
+	cached, ok := cache[policyId]
+	cacheMu.RUnlock()
+	if ok && cached.digest == digest {
+		return cached, nil
+	}
+
+	// The strings are owned by the caller, so they are copied before cached.
+	cached, err := prepareQuery(strings.Clone(digest), strings.Clone(policy))
+	if err != nil {
+		return nil, err
+	}
+
+	cacheMu.Lock()
+	cache[strings.Clone(policyId)] = cached
+	cacheMu.Unlock()
+
+	return cached, nil
+}
+
+// newInstance prepares the query with the compiled policy, s.t. only the
+// query itself is compiled.
+func (c *cachedQuery) newInstance(ctx context.Context) (*instance, error) {
+	store := inmem.New()
+	query, err := rego.New(
+		rego.Query("input;data.policy"),
+		rego.Compiler(c.compiler),
+		rego.Store(store),
+	).PrepareForEval(ctx)
+	if err != nil {
+		return nil, err
+	}
+
+	return &instance{query: query, store: store}, nil
+}
+
+func (c *cachedQuery) eval(ctx context.Context, data map[string]interface{}, input map[string]interface{}) (rego.ResultSet, error) {
+	inst, ok := c.pool.Get().(*instance)
+	if !ok {
+		var err error
+		if inst, err = c.newInstance(ctx); err != nil {
+			return nil, err
+		}
+	}
+	defer c.pool.Put(inst)
+
+	// The reference data is written in a transaction which is never
+	// committed, s.t. the evaluations do not see each other's data. The store
+	// is only used by this evaluation, so the transaction does not block the
+	// other evaluations.
+	txn, err := inst.store.NewTransaction(ctx, storage.WriteParams)
+	if err != nil {
+		return nil, err
+	}
+	defer inst.store.Abort(ctx, txn)
+
+	if err := inst.store.Write(ctx, txn, storage.AddOp, storage.Path{}, data); err != nil {
+		return nil, err
+	}
+
+	return inst.query.Eval(ctx, rego.EvalInput(input), rego.EvalTransaction(txn))
+}
+
 //export evaluateGo
-func evaluateGo(policy string, data string, input string) *C.char {
+func evaluateGo(policyId string, digest string, policy string, data string, input string) *C.char {
 	// Deserialize the message in json format
 	input_map := make(map[string]interface{})
 	err := json.Unmarshal([]byte(input), &input_map)
@@ -22,32 +133,25 @@ func evaluateGo(policy string, data string, input string) *C.char {
 	data_map := make(map[string]interface{})
 	err2 := json.Unmarshal([]byte(data), &data_map)
 	if err2 != nil {
-		return C.CString("Error:: " + err.Error())
+		return C.CString("Error:: " + err2.Error())
 	}
-	// Manually create the storage layer. inmem.NewFromObject returns an
-	// in-memory store containing the supplied data.
-	store := inmem.NewFromObject(data_map)
 
-	// Construct a Rego object that can be prepared or evaluated.
-	r := rego.New(
-		rego.Query("input;data.policy"),
-		rego.Module("policy.rego", policy),
-		rego.Store(store),
-	)
-
-	// Create a prepared query that can be evaluated.
 	ctx := context.Background()
-	query, err := r.PrepareForEval(ctx)
+	query, err := getQuery(policyId, digest, policy)
 	if err != nil {
 		return C.CString("Error:: " + err.Error())
 	}
 
 	// Make opa query
-	rs, err := query.Eval(ctx, rego.EvalInput(input_map))
+	rs, err := query.eval(ctx, data_map, input_map)
 	if err != nil {
 		return C.CString("Error:: " + err.Error())
 	}
 
+	if len(rs) == 0 || len(rs[0].Expressions) < 2 {
+		return C.CString("Error:: policy is undefined, it must be in `package policy`")
+	}
+
 	dataOPA, ok := rs[0].Expressions[1].Value.(map[string]interface{})
 	if !ok {
 		return C.CString("Error:: unexpected type in second expression")
@@ -67,4 +171,49 @@ func evaluateGo(policy string, data string, input string) *C.char {
 	return C.CString(string(decision))
 }
 
+//export evictGo
+func evictGo(policyId string) {
+	cacheMu.Lock()
+	defer cacheMu.Unlock()
+
+	// An empty policyId evicts all the cached queries.
+	if policyId == "" {
+		cache = make(map[string]*cachedQuery)
+		return
+	}
+	delete(cache, policyId)
+}
+
+//export compileGo
+func compileGo(policy string) *C.char {
+	module, err := ast.ParseModule("policy.rego", policy)
+	if err != nil {
+		return C.CString("Error:: " + err.Error())
+	}
+	if module == nil {
+		return C.CString("Error:: empty policy")
+	}
+
+	// The attestation service queries `data.policy`.
+	if pkg := module.Package.Path.String(); pkg != "data.policy" {
+		return C.CString(fmt.Sprintf("Error:: policy must be in `package policy`, got `%s`", pkg))
+	}
+
+	compiler := ast.NewCompiler()
+	compiler.Compile(map[string]*ast.Module{"policy.rego": module})
+	if compiler.Failed() {
+		return C.CString("Error:: " + compiler.Errors.Error())
+	}
+
+	return C.CString("")
+}
+
+// freeGo frees a string returned by the exported functions. The caller owns
+// the returned strings and must free each of them exactly once.
+//
+//export freeGo
+func freeGo(s *C.char) {
+	C.free(unsafe.Pointer(s))
+}
+
 func main() {}
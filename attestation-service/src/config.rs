@@ -6,30 +6,65 @@ use std::convert::TryFrom;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
+use crate::nonce::ChallengeConfig;
+use crate::policy_engine::PolicyInputFormat;
 use crate::rvps::store::StoreType;
+use crate::verifier::{SevConfig, SnpConfig};
 
 /// Environment macro for Attestation Service work dir.
 const AS_WORK_DIR: &str = "AS_WORK_DIR";
 const DEFAULT_WORK_DIR: &str = "/opt/confidential-containers/attestation-service";
 
+#[cfg(feature = "opa-engine")]
+const DEFAULT_POLICY_ENGINE: &str = "opa";
+#[cfg(not(feature = "opa-engine"))]
+const DEFAULT_POLICY_ENGINE: &str = "regorus";
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     /// The location for Attestation Service to store data.
     pub work_dir: PathBuf,
 
     /// Policy Engine type.
+    ///
+    /// Possible values:
+    /// * `opa`: needs the `opa-engine` feature
+    /// * `regorus`: needs the `regorus-engine` feature
     pub policy_engine: String,
 
+    /// The format of the policy input.
+    ///
+    /// Possible values:
+    /// * `Flattened`: claims with flattened keys like `tdx.quote.body.mr_td`, the default
+    /// * `Nested`: the nested claims as parsed by the verifier
+    #[serde(default)]
+    pub policy_input: PolicyInputFormat,
+
     pub rvps_store_type: StoreType,
 
     /// The Attestation Result Token Broker type.
     ///
     /// Possible values:
     /// * `Simple`
+    /// * `Ear`
     pub attestation_token_broker: AttestationTokenBrokerType,
 
     /// The Attestation Result Token Broker Config
     pub attestation_token_config: AttestationTokenConfig,
+
+    /// Issue nonces as challenges, and only accept evidence bound to an
+    /// issued nonce which is neither expired nor used. If not set, the nonce
+    /// given by the caller is trusted.
+    #[serde(default)]
+    pub challenge: Option<ChallengeConfig>,
+
+    /// Configuration of the AMD SEV verifier.
+    #[serde(default)]
+    pub sev: SevConfig,
+
+    /// Configuration of the AMD SEV-SNP verifiers.
+    #[serde(default)]
+    pub snp: SnpConfig,
 }
 
 impl Default for Config {
@@ -41,10 +76,14 @@ impl Default for Config {
 
         Config {
             work_dir,
-            policy_engine: "opa".to_string(),
+            policy_engine: DEFAULT_POLICY_ENGINE.to_string(),
+            policy_input: PolicyInputFormat::default(),
             rvps_store_type: StoreType::LocalFs,
             attestation_token_broker: AttestationTokenBrokerType::Simple,
             attestation_token_config: AttestationTokenConfig::default(),
+            challenge: None,
+            sev: SevConfig::default(),
+            snp: SnpConfig::default(),
         }
     }
 }
@@ -54,10 +93,33 @@ impl TryFrom<&Path> for Config {
     ///    {
     ///        "work_dir": "/var/lib/attestation-service/",
     ///        "policy_engine": "opa",
+    ///        "policy_input": "Flattened",
     ///        "rvps_store_type": "LocalFs",
     ///        "attestation_token_broker": "Simple",
     ///        "attestation_token_config": {
-    ///            "duration_min": 5
+    ///            "duration_min": 5,
+    ///            "algorithm": "ES256",
+    ///            "encrypt_token": false,
+    ///            "signer": {
+    ///                "key_path": "/etc/attestation-service/token.key",
+    ///                "cert_path": "/etc/attestation-service/token.crt"
+    ///            },
+    ///            "retired_key_grace_min": 60
+    ///        },
+    ///        "challenge": {
+    ///            "nonce_ttl_sec": 60,
+    ///            "nonce_store_type": "Memory",
+    ///            "max_nonces": 100000
+    ///        },
+    ///        "sev": {
+    ///            "session_dir": "/var/lib/attestation-service/sev/sessions"
+    ///        },
+    ///        "snp": {
+    ///            "trusted_chains": ["/etc/attestation-service/amd/ask_ark.pem"],
+    ///            "cert_cache_dir": "/var/lib/attestation-service/snp/certs",
+    ///            "kds_url": "https://kdsintf.amd.com",
+    ///            "crls": ["/etc/attestation-service/amd/milan.crl"],
+    ///            "require_crl": false
     ///        }
     ///    }
     type Error = anyhow::Error;
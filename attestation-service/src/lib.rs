@@ -3,6 +3,9 @@
 //! # Features
 //! - `rvps-grpc`: The AS will connect a remote RVPS.
 //! - `rvps-native`: The AS will integrate RVPS functionalities itself.
+//! - `opa-engine`, `regorus-engine`: The policy engines of the AS. Without
+//!   either, e.g. for the standalone RVPS, no policy engine is built and the
+//!   AS cannot be created.
 
 extern crate serde;
 
@@ -15,6 +18,7 @@ extern crate strum;
 extern crate strum_macros;
 
 pub mod config;
+pub mod nonce;
 pub mod policy_engine;
 pub mod rvps;
 mod token;
@@ -27,10 +31,13 @@ use anyhow::{anyhow, Context, Result};
 use as_types::SetPolicyInput;
 use config::Config;
 pub use kbs_types::{Attestation, Tee};
-use policy_engine::PolicyEngine;
+use nonce::{Challenger, Nonce};
+use policy_engine::{PolicyDecision, PolicyEngine, PolicyInputFormat, PolicyVersion};
 use rvps::{Message, RVPSAPI};
 use serde_json::json;
-use std::collections::HashMap;
+use serde_variant::to_variant_name;
+use std::collections::{BTreeSet, HashMap};
+use verifier::Verifiers;
 
 #[cfg(any(feature = "rvps-grpc", feature = "rvps-native"))]
 use std::{fs, str::FromStr};
@@ -38,13 +45,37 @@ use std::{fs, str::FromStr};
 #[cfg(any(feature = "rvps-grpc", feature = "rvps-native"))]
 use policy_engine::PolicyEngineType;
 
-use crate::utils::flatten_claims;
+use crate::utils::{flatten_claims, merge_policy_claims};
 
+/// The policy used when no policy id is given in the attestation request.
+const DEFAULT_POLICY_ID: &str = "default";
+
+/// The error returned when the evidence is denied by a policy. It carries the
+/// signed attestation results token reporting the decisions, s.t. the denial
+/// can be audited.
+#[derive(Debug)]
+pub struct EvidenceDenied {
+    pub token: String,
+    pub reasons: Vec<String>,
+}
+
+impl std::fmt::Display for EvidenceDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Untrusted TEE evidence: {}", self.reasons.join("; "))
+    }
+}
+
+impl std::error::Error for EvidenceDenied {}
+
+/// The Attestation Service is internally synchronized, s.t. it can be shared,
+/// e.g. in an `Arc`, and all its methods can be called concurrently.
 pub struct AttestationService {
-    _config: Config,
+    config: Config,
     policy_engine: Box<dyn PolicyEngine + Send + Sync>,
     rvps: Box<dyn RVPSAPI + Send + Sync>,
     token_broker: Box<dyn AttestationTokenBroker + Send + Sync>,
+    challenger: Option<Challenger>,
+    verifiers: Verifiers,
 }
 
 impl AttestationService {
@@ -67,11 +98,17 @@ impl AttestationService {
             .attestation_token_broker
             .to_token_broker(config.attestation_token_config.clone())?;
 
+        let challenger = config.challenge.as_ref().map(Challenger::new).transpose()?;
+
+        let verifiers = Verifiers::new(&config)?;
+
         Ok(Self {
-            _config: config,
+            config,
             policy_engine,
             rvps,
             token_broker,
+            challenger,
+            verifiers,
         })
     }
 
@@ -93,28 +130,101 @@ impl AttestationService {
             .attestation_token_broker
             .to_token_broker(config.attestation_token_config.clone())?;
 
+        let challenger = config.challenge.as_ref().map(Challenger::new).transpose()?;
+
+        let verifiers = Verifiers::new(&config)?;
+
         Ok(Self {
-            _config: config,
+            config,
             policy_engine,
             rvps,
             token_broker,
+            challenger,
+            verifiers,
         })
     }
 
-    /// Set Attestation Verification Policy.
-    pub async fn set_policy(&mut self, input: SetPolicyInput) -> Result<()> {
+    /// Set Attestation Verification Policy. With a dry run in the input, the
+    /// policy is not stored, but the decision on the sample data is returned.
+    pub async fn set_policy(&self, input: SetPolicyInput) -> Result<Option<PolicyDecision>> {
         self.policy_engine
             .set_policy(input)
             .await
             .map_err(|e| anyhow!("Cannot Set Policy: {:?}", e))
     }
 
+    /// List the ids of all the Attestation Verification Policies.
+    pub async fn list_policies(&self) -> Result<Vec<String>> {
+        self.policy_engine
+            .list_policies()
+            .await
+            .map_err(|e| anyhow!("Cannot List Policies: {:?}", e))
+    }
+
+    /// Get the base64 encoded Attestation Verification Policy. The current
+    /// policy is returned if no `version` is given.
+    pub async fn get_policy(&self, policy_id: &str, version: Option<u64>) -> Result<String> {
+        self.policy_engine
+            .get_policy(policy_id, version)
+            .await
+            .map_err(|e| anyhow!("Cannot Get Policy: {:?}", e))
+    }
+
+    /// Delete an Attestation Verification Policy. Its history is kept.
+    pub async fn delete_policy(&self, policy_id: &str) -> Result<()> {
+        self.policy_engine
+            .delete_policy(policy_id)
+            .await
+            .map_err(|e| anyhow!("Cannot Delete Policy: {:?}", e))
+    }
+
+    /// Get the version history of an Attestation Verification Policy.
+    pub async fn policy_history(&self, policy_id: &str) -> Result<Vec<PolicyVersion>> {
+        self.policy_engine
+            .policy_history(policy_id)
+            .await
+            .map_err(|e| anyhow!("Cannot Get Policy History: {:?}", e))
+    }
+
+    /// Restore an Attestation Verification Policy to a previous version.
+    pub async fn rollback_policy(&self, policy_id: &str, version: u64) -> Result<()> {
+        self.policy_engine
+            .rollback_policy(policy_id, version)
+            .await
+            .map_err(|e| anyhow!("Cannot Rollback Policy: {:?}", e))
+    }
+
     /// Evaluate Attestation Evidence.
     /// Issue an attestation results token which contain TCB status and TEE public key.
-    pub async fn evaluate(&self, tee: Tee, nonce: &str, attestation: &str) -> Result<String> {
+    ///
+    /// The evidence is evaluated against each policy of `policy_ids`, or the
+    /// default policy if no policy id is given. The token reports the decision of
+    /// each policy. If any policy denies the evidence, an [`EvidenceDenied`] error
+    /// is returned, which still carries the token.
+    ///
+    /// `audience` and `subject` are set as the `aud` and `sub` claims of the token
+    /// if given, to bind the token to a specific relying party.
+    ///
+    /// If challenges are enabled, `nonce` must be a nonce issued by
+    /// [`AttestationService::issue_nonce`], which is neither expired nor used.
+    pub async fn evaluate(
+        &self,
+        tee: Tee,
+        nonce: &str,
+        attestation: &str,
+        policy_ids: Vec<String>,
+        audience: Option<&str>,
+        subject: Option<&str>,
+    ) -> Result<String> {
+        if let Some(challenger) = &self.challenger {
+            challenger
+                .redeem(nonce)
+                .map_err(|e| anyhow!("Nonce check failed: {e}"))?;
+        }
+
         let attestation = serde_json::from_str::<Attestation>(attestation)
             .context("Failed to deserialize Attestation")?;
-        let verifier = crate::verifier::to_verifier(&tee)?;
+        let verifier = self.verifiers.get(&tee)?;
 
         let claims_from_tee_evidence = verifier
             .evaluate(nonce.to_string(), &attestation)
@@ -122,48 +232,153 @@ impl AttestationService {
             .map_err(|e| anyhow!("Verifier evaluate failed: {e:?}"))?;
 
         let flattened_claims = flatten_claims(tee.clone(), &claims_from_tee_evidence)?;
-        let tcb = serde_json::to_string(&flattened_claims)?;
+        let policy_input = match self.config.policy_input {
+            PolicyInputFormat::Flattened => flattened_claims.to_string(),
+            PolicyInputFormat::Nested => {
+                json!({ to_variant_name(&tee)?: claims_from_tee_evidence }).to_string()
+            }
+        };
+
+        let policy_ids = match policy_ids.is_empty() {
+            true => vec![DEFAULT_POLICY_ID.to_string()],
+            false => policy_ids,
+        };
         let reference_data_map = self
-            .get_reference_data(&tcb)
+            .get_reference_data(&policy_ids, &flattened_claims)
             .await
             .map_err(|e| anyhow!("Generate reference data failed{:?}", e))?;
+        let mut evaluation_reports = Vec::new();
+        let mut policy_claims = serde_json::Map::new();
+        let mut reasons = Vec::new();
+        let mut allow = true;
+        for policy_id in policy_ids {
+            let decision = self
+                .policy_engine
+                .evaluate(
+                    reference_data_map.clone(),
+                    policy_input.clone(),
+                    Some(policy_id.clone()),
+                )
+                .await
+                .map_err(|e| anyhow!("Policy Engine evaluation of {policy_id} failed: {e}"))?;
+            if !decision.allow {
+                allow = false;
+                match decision.reasons.is_empty() {
+                    true => reasons.push(format!("{policy_id}: evidence denied")),
+                    false => reasons.extend(
+                        decision
+                            .reasons
+                            .iter()
+                            .map(|reason| format!("{policy_id}: {reason}")),
+                    ),
+                }
+            }
+            merge_policy_claims(&mut policy_claims, &policy_id, &decision.claims);
+            evaluation_reports.push(json!({
+                "policy-id": policy_id,
+                "evaluation-report": decision,
+            }));
+        }
 
-        // Now only support using default policy to evaluate
-        let evaluation_report = self
-            .policy_engine
-            .evaluate(reference_data_map, tcb.clone(), None)
-            .await
-            .map_err(|e| anyhow!("Policy Engine evaluation failed: {e}"))?;
-
-        let token_claims = json!({
+        let mut token_claims = json!({
+            "tee": to_variant_name(&tee)?,
             "tee-pubkey": attestation.tee_pubkey.clone(),
             "tcb-status": flattened_claims,
-            "evaluation-report": evaluation_report,
+            "allow": allow,
+            "evaluation-reports": evaluation_reports,
         });
+        if let Some(audience) = audience {
+            token_claims["aud"] = json!(audience);
+        }
+        if let Some(subject) = subject {
+            token_claims["sub"] = json!(subject);
+        }
+        if let Some(token_claims) = token_claims.as_object_mut() {
+            token_claims.extend(policy_claims);
+        }
         let attestation_results_token = self.token_broker.issue(token_claims)?;
+        if !allow {
+            return Err(EvidenceDenied {
+                token: attestation_results_token,
+                reasons,
+            }
+            .into());
+        }
 
         Ok(attestation_results_token)
     }
 
-    async fn get_reference_data(&self, tcb_claims: &str) -> Result<HashMap<String, Vec<String>>> {
-        let mut data = HashMap::new();
-        let tcb_claims_map: HashMap<String, String> = serde_json::from_str(tcb_claims)?;
-        for key in tcb_claims_map.keys() {
-            data.insert(
-                key.to_string(),
-                self.rvps
-                    .get_digests(key)
-                    .await?
-                    .unwrap_or_default()
-                    .hash_values
-                    .clone(),
-            );
+    /// Get the reference values needed by the policies. A policy which does not
+    /// declare the reference values it needs gets the reference values of all
+    /// the flattened claims. The claim values can be of any JSON type, only the
+    /// keys are used.
+    async fn get_reference_data(
+        &self,
+        policy_ids: &[String],
+        flattened_claims: &serde_json::Value,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let claims = flattened_claims
+            .as_object()
+            .ok_or_else(|| anyhow!("Flattened claims must be a map"))?;
+
+        let mut names = BTreeSet::new();
+        for policy_id in policy_ids {
+            match self.policy_engine.reference_value_names(policy_id).await? {
+                Some(declared) => names.extend(declared),
+                None => names.extend(claims.keys().cloned()),
+            }
         }
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut digests = self.rvps.get_digests_batch(&names).await?;
+
+        let data = names
+            .into_iter()
+            .map(|name| {
+                let hash_values = digests
+                    .remove(name)
+                    .map(|digest| digest.hash_values)
+                    .unwrap_or_default();
+                (name.to_string(), hash_values)
+            })
+            .collect();
         Ok(data)
     }
 
+    /// Get the public keys of the attestation token broker in JWKS format.
+    pub fn token_pubkey_jwks(&self) -> Result<String> {
+        self.token_broker.pubkey_jwks()
+    }
+
+    /// Rotate the signing key of the attestation token broker.
+    pub fn rotate_token_key(&self) -> Result<()> {
+        self.token_broker
+            .rotate_key()
+            .map_err(|e| anyhow!("Cannot rotate token signing key: {:?}", e))
+    }
+
+    /// Verify an attestation token issued by this Attestation Service.
+    /// Return the claims of the token if the token is valid.
+    ///
+    /// A token bound to a relying party by its `aud` claim is only valid if
+    /// `audience` is given and is one of its audiences. A token reporting
+    /// denied evidence, which is issued for auditing, is not valid.
+    pub fn verify_token(&self, token: &str, audience: Option<&str>) -> Result<serde_json::Value> {
+        self.token_broker
+            .verify(token, audience)
+            .map_err(|e| anyhow!("Attestation token verification failed: {e}"))
+    }
+
+    /// Issue a nonce as the challenge of an attestation.
+    pub fn issue_nonce(&self) -> Result<Nonce> {
+        self.challenger
+            .as_ref()
+            .ok_or_else(|| anyhow!("Challenges are not enabled"))?
+            .issue()
+            .map_err(|e| anyhow!("Cannot issue nonce: {e}"))
+    }
+
     /// Registry a new reference value
-    pub async fn registry_reference_value(&mut self, message: Message) -> Result<()> {
+    pub async fn registry_reference_value(&self, message: Message) -> Result<()> {
         self.rvps.verify_and_extract(message).await
     }
 }
@@ -0,0 +1,99 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! This NonceStore keeps the nonces in memory.
+
+use anyhow::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use time::OffsetDateTime;
+
+use super::NonceStore;
+
+/// `MemoryNonceStore` implements [`NonceStore`] with a map from the nonces to
+/// their expiration time, and a queue of the nonces in the order they are
+/// inserted. As the nonces are issued with the same TTL, this is also the
+/// order they expire in, s.t. the expired nonces are dropped from the front
+/// of the queue when a new nonce is inserted, without scanning the map.
+///
+/// At most `max_nonces` nonces are kept until they expire, whether they are
+/// used or not, and more nonces are rejected until some of them expire.
+pub struct MemoryNonceStore {
+    max_nonces: usize,
+    nonces: Mutex<Nonces>,
+}
+
+#[derive(Default)]
+struct Nonces {
+    expiration: HashMap<String, OffsetDateTime>,
+    queue: VecDeque<(OffsetDateTime, String)>,
+}
+
+impl MemoryNonceStore {
+    pub fn new(max_nonces: usize) -> Self {
+        Self {
+            max_nonces,
+            nonces: Mutex::default(),
+        }
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn insert(&self, nonce: &str, expires_at: OffsetDateTime) -> Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let mut nonces = self
+            .nonces
+            .lock()
+            .map_err(|_| anyhow!("Nonce store lock poisoned"))?;
+        while let Some((expired, _)) = nonces.queue.front() {
+            if *expired > now {
+                break;
+            }
+            if let Some((_, nonce)) = nonces.queue.pop_front() {
+                nonces.expiration.remove(&nonce);
+            }
+        }
+
+        if nonces.queue.len() >= self.max_nonces {
+            bail!("Too many nonces issued, at most {}", self.max_nonces);
+        }
+        nonces.queue.push_back((expires_at, nonce.to_string()));
+        nonces.expiration.insert(nonce.to_string(), expires_at);
+
+        Ok(())
+    }
+
+    fn take(&self, nonce: &str) -> Result<Option<OffsetDateTime>> {
+        Ok(self
+            .nonces
+            .lock()
+            .map_err(|_| anyhow!("Nonce store lock poisoned"))?
+            .expiration
+            .remove(nonce))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    #[test]
+    fn test_max_nonces() {
+        let store = MemoryNonceStore::new(2);
+        let now = OffsetDateTime::now_utc();
+        store.insert("expired", now - Duration::seconds(1)).unwrap();
+        store.insert("first", now + Duration::seconds(60)).unwrap();
+
+        // The expired nonce is dropped to make room.
+        store.insert("second", now + Duration::seconds(60)).unwrap();
+        assert!(store.take("expired").unwrap().is_none());
+
+        // A used nonce still counts until it expires.
+        assert!(store.take("first").unwrap().is_some());
+        assert!(store.insert("third", now + Duration::seconds(60)).is_err());
+        assert!(store.take("second").unwrap().is_some());
+    }
+}
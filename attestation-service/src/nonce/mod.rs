@@ -0,0 +1,167 @@
+// Copyright (c) 2023 Alibaba Cloud
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Nonces issued by the Attestation Service as challenges, s.t. the freshness
+//! of the evidence can be checked. Each nonce expires after a TTL and can be
+//! used for a single attestation only.
+
+use anyhow::*;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use strum_macros::EnumString;
+use time::{Duration, OffsetDateTime};
+
+mod memory;
+
+const DEFAULT_NONCE_TTL_SEC: i64 = 60;
+const DEFAULT_MAX_NONCES: usize = 100_000;
+const NONCE_LEN: usize = 32;
+
+/// Interface of a `NonceStore`, which keeps the issued nonces until they are
+/// used or expired. A store must be safe to be used concurrently, and taking
+/// a nonce must be atomic, s.t. a nonce cannot be used twice.
+pub trait NonceStore {
+    /// Keep the nonce until `expires_at`.
+    fn insert(&self, nonce: &str, expires_at: OffsetDateTime) -> Result<()>;
+
+    /// Remove the nonce from the store. Return its expiration time, or `None`
+    /// if the nonce was not issued or has been taken.
+    fn take(&self, nonce: &str) -> Result<Option<OffsetDateTime>>;
+}
+
+#[derive(Deserialize, Debug, Clone, Default, EnumString)]
+pub enum NonceStoreType {
+    #[default]
+    Memory,
+}
+
+impl NonceStoreType {
+    pub fn to_nonce_store(&self, max_nonces: usize) -> Result<Box<dyn NonceStore + Send + Sync>> {
+        match self {
+            NonceStoreType::Memory => Ok(Box::new(memory::MemoryNonceStore::new(max_nonces))
+                as Box<dyn NonceStore + Send + Sync>),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChallengeConfig {
+    /// How long(in second) an issued nonce can be used. Defaults to 60.
+    #[serde(default = "default_nonce_ttl_sec")]
+    pub nonce_ttl_sec: i64,
+
+    /// The store of the issued nonces. Defaults to `Memory`, which does not
+    /// share the nonces between Attestation Service instances.
+    #[serde(default)]
+    pub nonce_store_type: NonceStoreType,
+
+    /// How many nonces can be issued within the TTL. More nonces are rejected
+    /// until some of them expire, s.t. the store cannot be flooded. Defaults
+    /// to 100000.
+    #[serde(default = "default_max_nonces")]
+    pub max_nonces: usize,
+}
+
+fn default_nonce_ttl_sec() -> i64 {
+    DEFAULT_NONCE_TTL_SEC
+}
+
+fn default_max_nonces() -> usize {
+    DEFAULT_MAX_NONCES
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        Self {
+            nonce_ttl_sec: DEFAULT_NONCE_TTL_SEC,
+            nonce_store_type: NonceStoreType::default(),
+            max_nonces: DEFAULT_MAX_NONCES,
+        }
+    }
+}
+
+/// A nonce issued as a challenge.
+#[derive(Debug, Clone)]
+pub struct Nonce {
+    pub value: String,
+    pub expires_at: OffsetDateTime,
+}
+
+/// Issues the nonces and checks the nonces used in attestations.
+pub struct Challenger {
+    store: Box<dyn NonceStore + Send + Sync>,
+    ttl: Duration,
+}
+
+impl Challenger {
+    pub fn new(config: &ChallengeConfig) -> Result<Self> {
+        if config.nonce_ttl_sec <= 0 {
+            bail!("Illegal nonce TTL {}", config.nonce_ttl_sec);
+        }
+        if config.max_nonces == 0 {
+            bail!("Illegal max nonces {}", config.max_nonces);
+        }
+
+        Ok(Self {
+            store: config.nonce_store_type.to_nonce_store(config.max_nonces)?,
+            ttl: Duration::seconds(config.nonce_ttl_sec),
+        })
+    }
+
+    /// Issue a random nonce, which expires after the TTL.
+    pub fn issue(&self) -> Result<Nonce> {
+        let mut bytes = [0u8; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut bytes)?;
+        let nonce = Nonce {
+            value: URL_SAFE_NO_PAD.encode(bytes),
+            expires_at: OffsetDateTime::now_utc() + self.ttl,
+        };
+        self.store.insert(&nonce.value, nonce.expires_at)?;
+
+        Ok(nonce)
+    }
+
+    /// Use a nonce for an attestation. Unknown, expired or already used
+    /// nonces are rejected.
+    pub fn redeem(&self, nonce: &str) -> Result<()> {
+        let expires_at = self
+            .store
+            .take(nonce)?
+            .ok_or_else(|| anyhow!("Nonce is unknown or has been used"))?;
+        if OffsetDateTime::now_utc() >= expires_at {
+            bail!("Nonce has expired at {expires_at}");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redeem_once() {
+        let challenger = Challenger::new(&ChallengeConfig::default()).unwrap();
+        let nonce = challenger.issue().unwrap();
+        assert_ne!(nonce.value, challenger.issue().unwrap().value);
+
+        challenger.redeem(&nonce.value).unwrap();
+        assert!(challenger.redeem(&nonce.value).is_err());
+        assert!(challenger.redeem("unknown").is_err());
+    }
+
+    #[test]
+    fn test_redeem_expired() {
+        let challenger = Challenger::new(&ChallengeConfig::default()).unwrap();
+        challenger
+            .store
+            .insert("expired", OffsetDateTime::now_utc() - Duration::seconds(1))
+            .unwrap();
+
+        assert!(challenger.redeem("expired").is_err());
+    }
+}
@@ -1,16 +1,34 @@
 use anyhow::Result;
 use as_types::SetPolicyInput;
 use async_trait::async_trait;
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 use std::path::Path;
 
+#[cfg(any(feature = "opa-engine", feature = "regorus-engine"))]
 pub mod opa;
 
 #[derive(Debug, EnumString, Deserialize)]
 #[strum(ascii_case_insensitive)]
 pub enum PolicyEngineType {
+    /// OPA library linked through cgo.
     OPA,
+    /// Native Rust Rego interpreter, which needs no Go toolchain.
+    Regorus,
+}
+
+/// The format of the parsed claims of the evidence given as the policy input.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum PolicyInputFormat {
+    /// Key value pairs with the flattened claim paths as keys, e.g.
+    /// `{"tdx.quote.body.mr_td": "..."}`.
+    #[default]
+    Flattened,
+    /// The claims as parsed by the verifier, under the TEE type, e.g.
+    /// `{"tdx": {"quote": {"body": {"mr_td": "..."}}}}`.
+    Nested,
 }
 
 #[derive(Debug, EnumString, Deserialize, PartialEq)]
@@ -21,22 +39,120 @@ pub enum PolicyType {
 
 impl PolicyEngineType {
     #[allow(dead_code)]
-    pub fn to_policy_engine(&self, work_dir: &Path) -> Result<Box<dyn PolicyEngine + Send + Sync>> {
+    pub fn to_policy_engine(
+        &self,
+        #[allow(unused_variables)] work_dir: &Path,
+    ) -> Result<Box<dyn PolicyEngine + Send + Sync>> {
         match self {
-            PolicyEngineType::OPA => Ok(Box::new(opa::OPA::new(work_dir.to_path_buf())?)
-                as Box<dyn PolicyEngine + Send + Sync>),
+            PolicyEngineType::OPA => {
+                cfg_if::cfg_if! {
+                    if #[cfg(feature = "opa-engine")] {
+                        Ok(Box::new(opa::OPA::new_with_rego(
+                            work_dir.to_path_buf(),
+                            Box::<opa::cgo::Cgo>::default(),
+                        )?) as Box<dyn PolicyEngine + Send + Sync>)
+                    } else {
+                        anyhow::bail!("feature `opa-engine` is not enabled!")
+                    }
+                }
+            }
+            PolicyEngineType::Regorus => {
+                cfg_if::cfg_if! {
+                    if #[cfg(feature = "regorus-engine")] {
+                        Ok(Box::new(opa::OPA::new_with_rego(
+                            work_dir.to_path_buf(),
+                            Box::<opa::native::Regorus>::default(),
+                        )?) as Box<dyn PolicyEngine + Send + Sync>)
+                    } else {
+                        anyhow::bail!("feature `regorus-engine` is not enabled!")
+                    }
+                }
+            }
         }
     }
 }
 
+/// The decision of a policy on the claims of a TEE evidence, taken from the
+/// rules of the policy:
+/// * `allow`: whether the evidence is accepted. A policy without an `allow`
+///   rule denies any evidence.
+/// * `reasons`: why the evidence is denied, e.g. one message for each failed rule.
+/// * `denied_claims`: the keys of the claims which do not satisfy the policy.
+/// * `claims`: custom claims derived by the policy, e.g. a workload identity,
+///   which are added to the attestation token as top-level claims.
+/// * `outputs`: all the other rules of the policy.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PolicyDecision {
+    #[serde(default)]
+    pub allow: bool,
+    #[serde(default)]
+    pub reasons: Vec<String>,
+    #[serde(default)]
+    pub denied_claims: Vec<String>,
+    #[serde(default)]
+    pub claims: Map<String, Value>,
+    #[serde(flatten)]
+    pub outputs: Map<String, Value>,
+}
+
+/// The operation recorded in a policy version.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Set,
+    Rollback,
+    Delete,
+}
+
+/// A version in the history of a policy.
+/// * `version`: version number, starting from 1 and increased by each write.
+/// * `action`: the operation that created this version.
+/// * `digest`: SHA-384 digest of the policy content, empty if deleted.
+/// * `created`: time when this version was created.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PolicyVersion {
+    pub version: u64,
+    pub action: PolicyAction,
+    pub digest: String,
+    pub created: DateTime<Utc>,
+}
+
+/// A policy engine is shared by concurrent attestations, so all the methods
+/// take `&self`. A policy which is changed is swapped atomically, i.e. an
+/// evaluation sees either the previous or the new version of the policy.
 #[async_trait]
 pub trait PolicyEngine {
+    /// Evaluate the input claims against the policy. A denial of the policy
+    /// is a successful evaluation, returned as a decision which does not allow.
     async fn evaluate(
         &self,
         reference_data_map: HashMap<String, Vec<String>>,
         input: String,
         policy_id: Option<String>,
-    ) -> Result<String>;
+    ) -> Result<PolicyDecision>;
+
+    /// Check and store the policy. If the input has a dry run, the policy is
+    /// only evaluated against its sample data, and the decision is returned
+    /// without storing the policy.
+    async fn set_policy(&self, input: SetPolicyInput) -> Result<Option<PolicyDecision>>;
+
+    /// Get the names of the reference values the policy needs, or `None` if
+    /// the policy does not declare them, s.t. the reference values of all the
+    /// claims are needed.
+    async fn reference_value_names(&self, policy_id: &str) -> Result<Option<Vec<String>>>;
+
+    /// List the ids of all the stored policies.
+    async fn list_policies(&self) -> Result<Vec<String>>;
+
+    /// Get the base64 encoded content of a policy. If `version` is not given,
+    /// the current policy is returned, otherwise the given version of its history.
+    async fn get_policy(&self, policy_id: &str, version: Option<u64>) -> Result<String>;
+
+    async fn delete_policy(&self, policy_id: &str) -> Result<()>;
+
+    /// Get the version history of a policy, oldest first.
+    async fn policy_history(&self, policy_id: &str) -> Result<Vec<PolicyVersion>>;
 
-    async fn set_policy(&mut self, input: SetPolicyInput) -> Result<()>;
+    /// Restore a previous version of a policy, which is recorded as a new version.
+    async fn rollback_policy(&self, policy_id: &str, version: u64) -> Result<()>;
 }
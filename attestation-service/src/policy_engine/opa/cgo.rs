@@ -0,0 +1,145 @@
+//! Rego backend calling the Go OPA library through cgo.
+
+use anyhow::{anyhow, bail, Result};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+use super::Rego;
+
+// Link import cgo function
+#[link(name = "cgo")]
+extern "C" {
+    pub fn evaluateGo(
+        policy_id: GoString,
+        digest: GoString,
+        policy: GoString,
+        data: GoString,
+        input: GoString,
+    ) -> *mut c_char;
+    pub fn evictGo(policy_id: GoString);
+    pub fn compileGo(policy: GoString) -> *mut c_char;
+    pub fn freeGo(s: *mut c_char);
+}
+
+/// String structure passed into cgo
+#[derive(Debug)]
+#[repr(C)]
+pub struct GoString {
+    pub p: *const c_char,
+    pub n: isize,
+}
+
+impl GoString {
+    fn new(s: &str) -> Self {
+        Self {
+            p: s.as_ptr() as *const c_char,
+            n: s.len() as isize,
+        }
+    }
+}
+
+/// Take the string returned by a function exported by cgo, and free its buffer.
+fn take_go_string(buf: *mut c_char) -> Result<String> {
+    if buf.is_null() {
+        bail!("cgo returned a null string");
+    }
+
+    let res = unsafe { CStr::from_ptr(buf) }.to_str().map(str::to_string);
+    unsafe { freeGo(buf) };
+
+    Ok(res?)
+}
+
+/// The prepared queries of the policies are cached by cgo under the policy
+/// id and the digest of the policy content.
+#[derive(Debug, Default)]
+pub struct Cgo;
+
+impl Rego for Cgo {
+    fn compile(&self, policy: &str) -> Result<()> {
+        let res = take_go_string(unsafe { compileGo(GoString::new(policy)) })?;
+        if let Some(diagnostics) = res.strip_prefix("Error:: ") {
+            bail!("Invalid OPA policy: {diagnostics}");
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(
+        &self,
+        policy_id: &str,
+        digest: &str,
+        policy: &str,
+        data: &str,
+        input: &str,
+    ) -> Result<String> {
+        // Call the function exported by cgo and process the returned decision
+        let decision_buf: *mut c_char = unsafe {
+            evaluateGo(
+                GoString::new(policy_id),
+                GoString::new(digest),
+                GoString::new(policy),
+                GoString::new(data),
+                GoString::new(input),
+            )
+        };
+        let res = take_go_string(decision_buf)?;
+        if res.starts_with("Error::") {
+            return Err(anyhow!(res));
+        }
+
+        Ok(res)
+    }
+
+    fn evict(&self, policy_id: &str) {
+        unsafe { evictGo(GoString::new(policy_id)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Resident set size of the process in pages.
+    #[cfg(target_os = "linux")]
+    fn resident_pages() -> u64 {
+        let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+        statm.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_evaluate_does_not_leak() {
+        const ROUNDS: u64 = 2000;
+        const BLOB_LEN: usize = 64 * 1024;
+
+        let cgo = Cgo;
+        let policy = "package policy\ndefault allow = true\necho := input.blob";
+        let input = json!({ "blob": "a".repeat(BLOB_LEN) }).to_string();
+        let data = json!({ "reference": {} }).to_string();
+        let evaluate = || {
+            let decision = cgo
+                .evaluate("leak-test", "digest", policy, &data, &input)
+                .unwrap();
+            assert!(decision.len() > BLOB_LEN);
+        };
+
+        // Warm up, s.t. the policy cache and the Go heap are settled.
+        for _ in 0..ROUNDS / 10 {
+            evaluate();
+        }
+        let before = resident_pages();
+        for _ in 0..ROUNDS {
+            evaluate();
+        }
+        let grown = resident_pages().saturating_sub(before) * 4096;
+
+        // Leaking the decisions would grow the process by ROUNDS * BLOB_LEN,
+        // i.e. 128 MiB.
+        assert!(
+            grown < ROUNDS * BLOB_LEN as u64 / 4,
+            "process grown by {grown} bytes"
+        );
+    }
+}
@@ -11,6 +11,15 @@
 #		...
 #	}
 #
+#   or the nested claims of the evidence, which are flattened by the policy:
+#
+#	{
+#		"sample": {
+#			"svn": 1,
+#			...
+#		}
+#	}
+#
 # - The format of reference data required by this default policy is defined as follows:
 #
 #	{
@@ -31,8 +40,27 @@ import future.keywords.every
 
 default allow = false
 
+# `flattened_input`: the input as key value pairs. A nested input is flattened with '.' to separate
+# the keys, e.g. `{"a": {"b": 1}}` is judged as `{"a.b": "1"}`. Non-string values are
+# judged in their string form.
+flattened_input[k] := v {
+	walk(input, [path, value])
+	not is_object(value)
+	not is_array(value)
+	k := concat(".", [sprintf("%v", [p]) | p := path[_]])
+	v := claim_value(value)
+}
+
+claim_value(value) := value {
+	is_string(value)
+}
+
+claim_value(value) := sprintf("%v", [value]) {
+	not is_string(value)
+}
+
 allow {
-	every k, v in input {
+	every k, v in flattened_input {
 		# `judge_field`: Traverse each key value pair in the input and make policy judgments on it.
 		#
 		# For each key value pair:
@@ -44,6 +72,20 @@ allow {
 	}
 }
 
+# `denied_claims`: the keys of the input claims which do not pass `judge_field`.
+denied_claims[k] {
+	some k
+	v := flattened_input[k]
+	not judge_field(k, v)
+}
+
+# `reasons`: why the evidence is denied, one message for each denied claim.
+reasons[msg] {
+	some k
+	denied_claims[k]
+	msg := sprintf("claim `%s` does not match the reference value", [k])
+}
+
 judge_field(input_key, input_value) {
 	has_key(data.reference, input_key)
 	reference_value := data.reference[input_key]
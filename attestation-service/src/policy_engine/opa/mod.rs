@@ -1,37 +1,95 @@
-use crate::policy_engine::{PolicyEngine, PolicyType};
+use crate::policy_engine::{PolicyAction, PolicyDecision, PolicyEngine, PolicyType, PolicyVersion};
 use anyhow::{anyhow, bail, Result};
 use as_types::SetPolicyInput;
 use async_trait::async_trait;
 use base64::Engine;
-use serde_json::Value;
+use sha2::{Digest, Sha384};
 use std::collections::HashMap;
-use std::ffi::CStr;
 use std::fs;
-use std::os::raw::c_char;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
 
-// Link import cgo function
-#[link(name = "cgo")]
-extern "C" {
-    pub fn evaluateGo(policy: GoString, data: GoString, input: GoString) -> *mut c_char;
+const DEFAULT_POLICY_ID: &str = "default";
+
+/// The rule of a policy to declare the names of the reference values it needs,
+/// e.g. `required_reference_values := ["tdx.quote.body.mr_td", "allowed_kernels"]`.
+/// It must not depend on the input.
+const REQUIRED_REFERENCE_VALUES_RULE: &str = "required_reference_values";
+
+/// Sub directory of the policy dir to keep the versions of the policies.
+const HISTORY_DIR: &str = "history";
+const HISTORY_INDEX: &str = "history.json";
+
+#[cfg(feature = "opa-engine")]
+pub mod cgo;
+#[cfg(feature = "regorus-engine")]
+pub mod native;
+
+/// The Rego interpreter used to compile and evaluate the policies.
+pub trait Rego: std::fmt::Debug + Send + Sync {
+    /// Check the policy, s.t. syntax and type errors are found before the
+    /// policy is used. The policy must be in `package policy`.
+    fn compile(&self, policy: &str) -> Result<()>;
+
+    /// Evaluate `data.policy` of the policy with the given data and input,
+    /// and return the JSON encoded decision. The interpreter may cache the
+    /// compiled policy under `policy_id` and `digest`, unless `policy_id`
+    /// is empty.
+    fn evaluate(
+        &self,
+        policy_id: &str,
+        digest: &str,
+        policy: &str,
+        data: &str,
+        input: &str,
+    ) -> Result<String>;
+
+    /// Drop the cached policy, or all the cached policies if `policy_id`
+    /// is empty.
+    fn evict(&self, policy_id: &str);
 }
 
-/// String structure passed into cgo
+/// A policy loaded from the policy dir. The reference values it declares are
+/// only resolved when they are asked for, s.t. loading the policy for an
+/// evaluation does not run another evaluation.
 #[derive(Debug)]
-#[repr(C)]
-pub struct GoString {
-    pub p: *const c_char,
-    pub n: isize,
+struct CachedPolicy {
+    source: String,
+    digest: String,
+    reference_value_names: OnceLock<Option<Vec<String>>>,
 }
 
+/// The OPA policy engine can be shared by concurrent evaluations and policy
+/// changes. The changes are serialized by `write_lock`, and the policy files
+/// are replaced atomically, s.t. an evaluation reads either the previous or
+/// the new version of a policy.
 #[derive(Debug)]
 pub struct OPA {
     policy_dir_path: PathBuf,
+    policies: RwLock<HashMap<String, Arc<CachedPolicy>>>,
+    /// Bumped on each eviction, s.t. a policy read before the eviction is
+    /// not put into the cache after it.
+    generation: AtomicU64,
+    write_lock: tokio::sync::Mutex<()>,
+    rego: Box<dyn Rego>,
 }
 
 impl OPA {
+    /// Create an OPA policy engine using the cgo OPA library, or regorus if
+    /// the `opa-engine` feature is not enabled.
     pub fn new(work_dir: PathBuf) -> Result<Self> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "opa-engine")] {
+                Self::new_with_rego(work_dir, Box::<cgo::Cgo>::default())
+            } else {
+                Self::new_with_rego(work_dir, Box::<native::Regorus>::default())
+            }
+        }
+    }
+
+    pub fn new_with_rego(work_dir: PathBuf, rego: Box<dyn Rego>) -> Result<Self> {
         let mut policy_dir_path = work_dir;
 
         policy_dir_path.push("opa");
@@ -51,68 +109,259 @@ impl OPA {
             fs::write(&default_policy_path, policy)?;
         }
 
-        Ok(Self { policy_dir_path })
+        Ok(Self {
+            policy_dir_path,
+            policies: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+            write_lock: tokio::sync::Mutex::new(()),
+            rego,
+        })
     }
-}
 
-#[async_trait]
-impl PolicyEngine for OPA {
-    async fn evaluate(
-        &self,
-        reference_data_map: HashMap<String, Vec<String>>,
-        input: String,
-        policy_id: Option<String>,
-    ) -> Result<String> {
-        let policy_file_path = format!(
-            "{}/{}.rego",
-            self.policy_dir_path
-                .to_str()
-                .ok_or_else(|| anyhow!("Miss Policy DirPath"))?,
-            policy_id.unwrap_or("default".to_string())
-        );
-        let policy = tokio::fs::read_to_string(policy_file_path)
+    /// Drop all the cached policies and their prepared queries. Needed if
+    /// the policy files are changed other than through the policy engine.
+    pub fn clear_cache(&self) -> Result<()> {
+        let mut policies = self
+            .policies
+            .write()
+            .map_err(|_| anyhow!("OPA policy cache lock poisoned"))?;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        policies.clear();
+        self.rego.evict("");
+
+        Ok(())
+    }
+
+    fn evict(&self, policy_id: &str) -> Result<()> {
+        let mut policies = self
+            .policies
+            .write()
+            .map_err(|_| anyhow!("OPA policy cache lock poisoned"))?;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        policies.remove(policy_id);
+        self.rego.evict(policy_id);
+
+        Ok(())
+    }
+
+    /// Get the policy from the cache, or load it from the policy dir.
+    async fn load_policy(&self, policy_id: &str) -> Result<Arc<CachedPolicy>> {
+        let cached = self
+            .policies
+            .read()
+            .map_err(|_| anyhow!("OPA policy cache lock poisoned"))?
+            .get(policy_id)
+            .cloned();
+        if let Some(policy) = cached {
+            return Ok(policy);
+        }
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let source = tokio::fs::read_to_string(self.policy_path(policy_id)?)
             .await
             .map_err(|e| anyhow!("Read OPA policy file failed: {:?}", e))?;
+        let digest = hex::encode(Sha384::digest(&source));
+        let policy = Arc::new(CachedPolicy {
+            source,
+            digest,
+            reference_value_names: OnceLock::new(),
+        });
 
-        let policy_go = GoString {
-            p: policy.as_ptr() as *const c_char,
-            n: policy.len() as isize,
-        };
+        let mut policies = self
+            .policies
+            .write()
+            .map_err(|_| anyhow!("OPA policy cache lock poisoned"))?;
+        if self.generation.load(Ordering::SeqCst) == generation {
+            policies.insert(policy_id.to_string(), policy.clone());
+        }
 
-        let reference = serde_json::json!({ "reference": reference_data_map }).to_string();
+        Ok(policy)
+    }
 
-        let reference_go = GoString {
-            p: reference.as_ptr() as *const c_char,
-            n: reference.len() as isize,
-        };
+    /// Policy ids are used as file names, so only alphanumeric characters,
+    /// `-` and `_` are allowed.
+    fn check_policy_id(policy_id: &str) -> Result<()> {
+        if policy_id.is_empty()
+            || !policy_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("Illegal policy id {policy_id}");
+        }
+
+        Ok(())
+    }
+
+    /// Get the path of the policy file.
+    fn policy_path(&self, policy_id: &str) -> Result<PathBuf> {
+        Self::check_policy_id(policy_id)?;
+        Ok(self.policy_dir_path.join(format!("{policy_id}.rego")))
+    }
+
+    /// Get the dir of the recorded versions of the policy.
+    fn history_dir(&self, policy_id: &str) -> Result<PathBuf> {
+        Self::check_policy_id(policy_id)?;
+        Ok(self.policy_dir_path.join(HISTORY_DIR).join(policy_id))
+    }
 
-        let input_go = GoString {
-            p: input.as_ptr() as *const c_char,
-            n: input.len() as isize,
+    async fn read_history(&self, policy_id: &str) -> Result<Vec<PolicyVersion>> {
+        let index_path = self.history_dir(policy_id)?.join(HISTORY_INDEX);
+        if !index_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let index = tokio::fs::read(&index_path)
+            .await
+            .map_err(|e| anyhow!("Read OPA policy history failed: {:?}", e))?;
+        Ok(serde_json::from_slice(&index)?)
+    }
+
+    /// Record a new version of the policy. `policy` is the content of the
+    /// new version, or `None` if the policy is deleted.
+    async fn record_version(
+        &self,
+        policy_id: &str,
+        action: PolicyAction,
+        policy: Option<&[u8]>,
+    ) -> Result<()> {
+        let mut history = self.read_history(policy_id).await?;
+        let version = history.last().map(|v| v.version).unwrap_or_default() + 1;
+
+        let history_dir = self.history_dir(policy_id)?;
+        tokio::fs::create_dir_all(&history_dir)
+            .await
+            .map_err(|e| anyhow!("Create OPA policy history dir failed: {:?}", e))?;
+
+        let digest = match policy {
+            Some(policy) => {
+                tokio::fs::write(history_dir.join(format!("{version}.rego")), policy)
+                    .await
+                    .map_err(|e| anyhow!("Write OPA policy history failed: {:?}", e))?;
+                hex::encode(Sha384::digest(policy))
+            }
+            None => String::new(),
         };
 
-        // Call the function exported by cgo and process the returned decision
-        let decision_buf: *mut c_char = unsafe { evaluateGo(policy_go, reference_go, input_go) };
-        let decision_str: &CStr = unsafe { CStr::from_ptr(decision_buf) };
-        let res = decision_str.to_str()?.to_string();
-        debug!("Evaluated: {}", res);
-        if res.starts_with("Error::") {
-            return Err(anyhow!(res));
+        history.push(PolicyVersion {
+            version,
+            action,
+            digest,
+            created: chrono::Utc::now(),
+        });
+        tokio::fs::write(
+            history_dir.join(HISTORY_INDEX),
+            serde_json::to_vec_pretty(&history)?,
+        )
+        .await
+        .map_err(|e| anyhow!("Write OPA policy history failed: {:?}", e))
+    }
+
+    async fn read_version(&self, policy_id: &str, version: u64) -> Result<Vec<u8>> {
+        let recorded = self
+            .read_history(policy_id)
+            .await?
+            .into_iter()
+            .any(|v| v.version == version && v.action != PolicyAction::Delete);
+        if !recorded {
+            bail!("Version {version} of policy {policy_id} not found");
         }
 
-        // If a clear approval opinion is given in the evaluation report,
-        // the rejection information will be reflected in the evaluation failure return value.
-        let res_kv: Value = serde_json::from_str(&res)?;
-        if let Some(allow) = res_kv["allow"].as_bool() {
-            if !allow {
-                bail!("Untrusted TEE evidence")
+        let path = self.history_dir(policy_id)?.join(format!("{version}.rego"));
+        tokio::fs::read(path)
+            .await
+            .map_err(|e| anyhow!("Read OPA policy history failed: {:?}", e))
+    }
+
+    /// Write the policy and record it as a new version. The policy is written
+    /// to a temporary file first, which then replaces the policy file. The
+    /// caller must hold `write_lock`.
+    async fn write_policy(
+        &self,
+        policy_id: &str,
+        action: PolicyAction,
+        policy: &[u8],
+    ) -> Result<()> {
+        let policy_file_path = self.policy_path(policy_id)?;
+        let temp_file_path = self.policy_dir_path.join(format!(".{policy_id}.rego.tmp"));
+        tokio::fs::write(&temp_file_path, policy)
+            .await
+            .map_err(|e| anyhow!("Write OPA policy to file failed: {:?}", e))?;
+        tokio::fs::rename(&temp_file_path, &policy_file_path)
+            .await
+            .map_err(|e| anyhow!("Replace OPA policy file failed: {:?}", e))?;
+        self.evict(policy_id)?;
+
+        self.record_version(policy_id, action, Some(policy)).await
+    }
+
+    /// Evaluate the `required_reference_values` rule of the policy without
+    /// input and reference data.
+    fn declared_reference_values(
+        &self,
+        policy_id: &str,
+        digest: &str,
+        policy: &str,
+    ) -> Option<Vec<String>> {
+        let decision = match self.evaluate_policy(policy_id, digest, policy, &HashMap::new(), "{}")
+        {
+            Ok(decision) => decision,
+            Err(e) => {
+                warn!("Cannot get the reference values required by policy {policy_id}: {e}");
+                return None;
+            }
+        };
+
+        let names = decision.outputs.get(REQUIRED_REFERENCE_VALUES_RULE)?;
+        match serde_json::from_value(names.clone()) {
+            Ok(names) => Some(names),
+            Err(e) => {
+                warn!("Illegal {REQUIRED_REFERENCE_VALUES_RULE} of policy {policy_id}: {e}");
+                None
             }
         }
+    }
+
+    /// Evaluate the policy with the given reference data and input.
+    fn evaluate_policy(
+        &self,
+        policy_id: &str,
+        digest: &str,
+        policy: &str,
+        reference_data_map: &HashMap<String, Vec<String>>,
+        input: &str,
+    ) -> Result<PolicyDecision> {
+        let reference = serde_json::json!({ "reference": reference_data_map }).to_string();
+
+        let res = self
+            .rego
+            .evaluate(policy_id, digest, policy, &reference, input)?;
+        debug!("Evaluated: {}", res);
+
+        serde_json::from_str(&res).map_err(|e| anyhow!("Illegal OPA policy decision: {e}"))
+    }
+}
+
+#[async_trait]
+impl PolicyEngine for OPA {
+    async fn evaluate(
+        &self,
+        reference_data_map: HashMap<String, Vec<String>>,
+        input: String,
+        policy_id: Option<String>,
+    ) -> Result<PolicyDecision> {
+        let policy_id = policy_id.unwrap_or(DEFAULT_POLICY_ID.to_string());
+        let policy = self.load_policy(&policy_id).await?;
 
-        Ok(res)
+        self.evaluate_policy(
+            &policy_id,
+            &policy.digest,
+            &policy.source,
+            &reference_data_map,
+            &input,
+        )
     }
 
-    async fn set_policy(&mut self, input: SetPolicyInput) -> Result<()> {
+    async fn set_policy(&self, input: SetPolicyInput) -> Result<Option<PolicyDecision>> {
         let policy_type = PolicyType::from_str(&input.r#type)
             .map_err(|_| anyhow!("{} is not support by AS", &input.r#type))?;
         if policy_type != PolicyType::Rego {
@@ -122,24 +371,111 @@ impl PolicyEngine for OPA {
         let policy_bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
             .decode(input.policy)
             .map_err(|e| anyhow!("Base64 decode OPA policy string failed: {:?}", e))?;
-        let mut policy_file_path = PathBuf::from(
-            &self
-                .policy_dir_path
-                .to_str()
-                .ok_or_else(|| anyhow!("Policy DirPath to string failed"))?,
-        );
-        policy_file_path.push(format!("{}.rego", input.policy_id));
+        let policy = std::str::from_utf8(&policy_bytes)
+            .map_err(|e| anyhow!("OPA policy is not UTF-8 encoded: {:?}", e))?;
+
+        self.rego.compile(policy)?;
+        if let Some(dry_run) = &input.dry_run {
+            // The policy is neither cached nor stored.
+            let decision = self
+                .evaluate_policy(
+                    "",
+                    "",
+                    policy,
+                    &dry_run.reference,
+                    &dry_run.input.to_string(),
+                )
+                .map_err(|e| anyhow!("Dry run of OPA policy failed: {e}"))?;
+            debug!("Dry run of OPA policy {}: {:?}", input.policy_id, decision);
+            return Ok(Some(decision));
+        }
+
+        let _guard = self.write_lock.lock().await;
+        self.write_policy(&input.policy_id, PolicyAction::Set, &policy_bytes)
+            .await?;
+
+        Ok(None)
+    }
+
+    async fn reference_value_names(&self, policy_id: &str) -> Result<Option<Vec<String>>> {
+        let policy = self.load_policy(policy_id).await?;
+        let names = policy.reference_value_names.get_or_init(|| {
+            self.declared_reference_values(policy_id, &policy.digest, &policy.source)
+        });
+
+        Ok(names.clone())
+    }
 
-        tokio::fs::write(&policy_file_path, policy_bytes)
+    async fn list_policies(&self) -> Result<Vec<String>> {
+        let mut entries = tokio::fs::read_dir(&self.policy_dir_path)
+            .await
+            .map_err(|e| anyhow!("Read policy dir failed: {:?}", e))?;
+
+        let mut policy_ids = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("rego") {
+                continue;
+            }
+            if let Some(policy_id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                policy_ids.push(policy_id.to_string());
+            }
+        }
+        policy_ids.sort();
+
+        Ok(policy_ids)
+    }
+
+    async fn get_policy(&self, policy_id: &str, version: Option<u64>) -> Result<String> {
+        let policy = match version {
+            Some(version) => self.read_version(policy_id, version).await?,
+            None => tokio::fs::read(self.policy_path(policy_id)?)
+                .await
+                .map_err(|_| anyhow!("Policy {policy_id} not found"))?,
+        };
+
+        Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy))
+    }
+
+    async fn delete_policy(&self, policy_id: &str) -> Result<()> {
+        if policy_id == DEFAULT_POLICY_ID {
+            bail!("The default policy cannot be deleted");
+        }
+
+        let policy_file_path = self.policy_path(policy_id)?;
+        let _guard = self.write_lock.lock().await;
+        if !policy_file_path.exists() {
+            bail!("Policy {policy_id} not found");
+        }
+        tokio::fs::remove_file(&policy_file_path)
+            .await
+            .map_err(|e| anyhow!("Remove OPA policy file failed: {:?}", e))?;
+        self.evict(policy_id)?;
+
+        self.record_version(policy_id, PolicyAction::Delete, None)
+            .await
+    }
+
+    async fn policy_history(&self, policy_id: &str) -> Result<Vec<PolicyVersion>> {
+        self.read_history(policy_id).await
+    }
+
+    async fn rollback_policy(&self, policy_id: &str, version: u64) -> Result<()> {
+        self.policy_path(policy_id)?;
+        let _guard = self.write_lock.lock().await;
+        let policy = self.read_version(policy_id, version).await?;
+
+        self.write_policy(policy_id, PolicyAction::Rollback, &policy)
             .await
-            .map_err(|e| anyhow!("Write OPA policy to file failed: {:?}", e))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use as_types::PolicyDryRun;
+    use rstest::rstest;
+    use serde_json::{json, Value};
 
     fn dummy_reference(ver: u64) -> String {
         json!({
@@ -159,9 +495,9 @@ mod tests {
 
     #[tokio::test]
     async fn test_evaluate() {
-        let opa = OPA {
-            policy_dir_path: PathBuf::from("./src/policy_engine/opa"),
-        };
+        let work_dir = tempfile::tempdir().unwrap();
+        let mut opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        opa.policy_dir_path = PathBuf::from("./src/policy_engine/opa");
         let default_policy_id = "default_policy".to_string();
 
         let reference_data: HashMap<String, Vec<String>> =
@@ -173,28 +509,373 @@ mod tests {
                 dummy_input(5, 5),
                 Some(default_policy_id.clone()),
             )
-            .await;
-        assert!(res.is_ok(), "OPA execution() should be success");
+            .await
+            .unwrap();
+        assert!(res.allow, "OPA execution() should be allowed");
+        assert!(res.denied_claims.is_empty());
 
         let res = opa
-            .evaluate(reference_data, dummy_input(0, 0), Some(default_policy_id))
-            .await;
-        assert!(res.is_err(), "OPA execution() should be failed");
+            .evaluate(reference_data, dummy_input(5, 0), Some(default_policy_id))
+            .await
+            .unwrap();
+        assert!(!res.allow, "OPA execution() should be denied");
+        assert_eq!(res.denied_claims, vec!["svn"]);
+        assert_eq!(res.reasons.len(), 1);
+    }
+
+    fn set_policy_input(policy_id: &str, policy: &str) -> SetPolicyInput {
+        SetPolicyInput {
+            r#type: "rego".to_string(),
+            policy_id: policy_id.to_string(),
+            policy: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy),
+            dry_run: None,
+        }
     }
 
     #[tokio::test]
     async fn test_set_policy() {
-        let mut opa = OPA::new(PathBuf::from("../test_data")).unwrap();
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
         let policy = "package policy
-default allow = true"
-            .to_string();
+default allow = true";
 
-        let input = SetPolicyInput {
-            r#type: "rego".to_string(),
-            policy_id: "test".to_string(),
-            policy: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(policy),
-        };
+        let input = set_policy_input("test", policy);
 
         assert!(opa.set_policy(input).await.is_ok());
+        assert!(opa
+            .set_policy(set_policy_input("../test", policy))
+            .await
+            .is_err());
+    }
+
+    #[rstest]
+    #[case(json!({"tdx.svn": 5, "tdx.debug": false, "tdx.rw": null}))]
+    #[case(json!({"tdx": {"svn": 5, "debug": false, "rw": null}}))]
+    #[tokio::test]
+    async fn test_evaluate_non_string_claims(#[case] input: Value) {
+        let work_dir = tempfile::tempdir().unwrap();
+        let mut opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        opa.policy_dir_path = PathBuf::from("./src/policy_engine/opa");
+        let default_policy_id = Some("default_policy".to_string());
+
+        let reference_data: HashMap<String, Vec<String>> = serde_json::from_value(json!({
+            "tdx.svn": ["5"],
+            "tdx.debug": ["false"],
+        }))
+        .unwrap();
+        let res = opa
+            .evaluate(reference_data, input.to_string(), default_policy_id.clone())
+            .await
+            .unwrap();
+        assert!(res.allow);
+
+        let reference_data: HashMap<String, Vec<String>> = serde_json::from_value(json!({
+            "tdx.svn": ["6"],
+            "tdx.debug": ["false"],
+        }))
+        .unwrap();
+        let res = opa
+            .evaluate(reference_data, input.to_string(), default_policy_id)
+            .await
+            .unwrap();
+        assert!(!res.allow);
+        assert_eq!(res.denied_claims, vec!["tdx.svn"]);
+    }
+
+    #[tokio::test]
+    async fn test_reference_value_names() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let policy = "package policy
+required_reference_values := [\"tdx.quote.body.mr_td\", \"allowed_kernels\"]
+default allow = false";
+        opa.set_policy(set_policy_input("test", policy))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            opa.reference_value_names("test").await.unwrap(),
+            Some(vec![
+                "tdx.quote.body.mr_td".to_string(),
+                "allowed_kernels".to_string()
+            ])
+        );
+        assert_eq!(opa.reference_value_names("default").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_policy_cache() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let policy_id = Some("test".to_string());
+
+        opa.set_policy(set_policy_input(
+            "test",
+            "package policy\ndefault allow = true",
+        ))
+        .await
+        .unwrap();
+        for _ in 0..2 {
+            assert!(
+                opa.evaluate(HashMap::new(), dummy_input(5, 5), policy_id.clone())
+                    .await
+                    .unwrap()
+                    .allow
+            );
+        }
+
+        // The cached policy is replaced by the new one.
+        opa.set_policy(set_policy_input(
+            "test",
+            "package policy\ndefault allow = false",
+        ))
+        .await
+        .unwrap();
+        assert!(
+            !opa.evaluate(HashMap::new(), dummy_input(5, 5), policy_id.clone())
+                .await
+                .unwrap()
+                .allow
+        );
+
+        opa.delete_policy("test").await.unwrap();
+        assert!(opa
+            .evaluate(HashMap::new(), dummy_input(5, 5), policy_id)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_set_and_evaluate() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = Arc::new(OPA::new(work_dir.path().to_path_buf()).unwrap());
+        let versions = ["version_1", "version_2"];
+        let policy =
+            |version: &str| format!("package policy\ndefault allow = true\n{version} := true");
+
+        opa.set_policy(set_policy_input("test", &policy(versions[0])))
+            .await
+            .unwrap();
+
+        let writer = {
+            let opa = opa.clone();
+            tokio::spawn(async move {
+                for round in 0..20 {
+                    let version = versions[round % 2];
+                    opa.set_policy(set_policy_input("test", &policy(version)))
+                        .await
+                        .unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let opa = opa.clone();
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        let decision = opa
+                            .evaluate(HashMap::new(), dummy_input(5, 5), Some("test".into()))
+                            .await
+                            .unwrap();
+                        let seen = versions
+                            .iter()
+                            .filter(|version| decision.outputs.contains_key(**version))
+                            .count();
+                        assert_eq!(seen, 1, "torn policy: {:?}", decision.outputs);
+                    }
+                })
+            })
+            .collect();
+
+        writer.await.unwrap();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+
+        // The last version is evaluated after all the changes, not a stale
+        // cached one, and every change is recorded.
+        let decision = opa
+            .evaluate(HashMap::new(), dummy_input(5, 5), Some("test".into()))
+            .await
+            .unwrap();
+        assert!(decision.outputs.contains_key(versions[1]));
+        assert_eq!(opa.policy_history("test").await.unwrap().len(), 21);
+    }
+
+    #[tokio::test]
+    async fn test_decision_outputs() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let policy = "package policy
+default allow = false
+reasons := [\"svn too old\"]
+denied_claims := [\"svn\"]
+claims := {\"tier\": \"bronze\"}
+tcb_level := \"low\"";
+        opa.set_policy(set_policy_input("test", policy))
+            .await
+            .unwrap();
+
+        let decision = opa
+            .evaluate(HashMap::new(), dummy_input(5, 5), Some("test".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            decision,
+            PolicyDecision {
+                allow: false,
+                reasons: vec!["svn too old".to_string()],
+                denied_claims: vec!["svn".to_string()],
+                claims: json!({"tier": "bronze"}).as_object().unwrap().clone(),
+                outputs: json!({"tcb_level": "low"}).as_object().unwrap().clone(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_without_allow() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let policy = "package policy
+tcb_level := \"low\"";
+        opa.set_policy(set_policy_input("test", policy))
+            .await
+            .unwrap();
+
+        let decision = opa
+            .evaluate(HashMap::new(), dummy_input(5, 5), Some("test".to_string()))
+            .await
+            .unwrap();
+        assert!(!decision.allow);
+        assert!(decision.outputs.contains_key("tcb_level"));
+    }
+
+    #[rstest]
+    #[case("package policy\ndefault allow = ")]
+    #[case("package other\ndefault allow = true")]
+    #[case("package policy\nallow { unknown_function(input.x) }")]
+    #[tokio::test]
+    async fn test_set_invalid_policy(#[case] policy: &str) {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+
+        assert!(opa
+            .set_policy(set_policy_input("test", policy))
+            .await
+            .is_err());
+        assert!(opa.get_policy("test", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_policy_dry_run() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let policy = "package policy
+default allow = false
+allow {
+    input.svn >= 1
+}";
+
+        let mut input = set_policy_input("test", policy);
+        input.dry_run = Some(PolicyDryRun {
+            input: json!({"svn": 0}),
+            reference: HashMap::new(),
+        });
+        let decision = opa.set_policy(input.clone()).await.unwrap().unwrap();
+        assert!(!decision.allow);
+
+        input.dry_run = Some(PolicyDryRun {
+            input: json!({"svn": 1}),
+            reference: HashMap::new(),
+        });
+        let decision = opa.set_policy(input.clone()).await.unwrap().unwrap();
+        assert!(decision.allow);
+        assert!(opa.get_policy("test", None).await.is_err());
+
+        // An invalid policy is still rejected.
+        input.policy = set_policy_input("test", "package policy\nallow {").policy;
+        assert!(opa.set_policy(input.clone()).await.is_err());
+
+        input.dry_run = None;
+        input.policy = set_policy_input("test", policy).policy;
+        assert!(opa.set_policy(input).await.unwrap().is_none());
+        assert!(opa.get_policy("test", None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_manage_policies() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let v1 = "package policy
+default allow = true";
+        let v2 = "package policy
+default allow = false";
+
+        opa.set_policy(set_policy_input("test", v1)).await.unwrap();
+        opa.set_policy(set_policy_input("test", v2)).await.unwrap();
+        assert_eq!(
+            opa.list_policies().await.unwrap(),
+            vec!["default".to_string(), "test".to_string()]
+        );
+        assert_eq!(
+            opa.get_policy("test", None).await.unwrap(),
+            set_policy_input("test", v2).policy
+        );
+        assert_eq!(
+            opa.get_policy("test", Some(1)).await.unwrap(),
+            set_policy_input("test", v1).policy
+        );
+
+        opa.rollback_policy("test", 1).await.unwrap();
+        assert_eq!(
+            opa.get_policy("test", None).await.unwrap(),
+            set_policy_input("test", v1).policy
+        );
+
+        opa.delete_policy("test").await.unwrap();
+        assert_eq!(opa.list_policies().await.unwrap(), vec!["default"]);
+        assert!(opa.get_policy("test", None).await.is_err());
+        assert!(opa.delete_policy("default").await.is_err());
+
+        let actions: Vec<PolicyAction> = opa
+            .policy_history("test")
+            .await
+            .unwrap()
+            .iter()
+            .map(|v| v.action)
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                PolicyAction::Set,
+                PolicyAction::Set,
+                PolicyAction::Rollback,
+                PolicyAction::Delete
+            ]
+        );
+
+        // A deleted policy can still be restored from its history.
+        opa.rollback_policy("test", 2).await.unwrap();
+        assert_eq!(
+            opa.get_policy("test", None).await.unwrap(),
+            set_policy_input("test", v2).policy
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_policy_version_traversal() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let opa = OPA::new(work_dir.path().to_path_buf()).unwrap();
+        let policy = "package policy
+default allow = true";
+
+        opa.set_policy(set_policy_input("test", policy))
+            .await
+            .unwrap();
+        assert!(opa.get_policy("test", Some(1)).await.is_ok());
+        for policy_id in ["..", "../history/test", "test/../test"] {
+            assert!(opa.get_policy(policy_id, Some(1)).await.is_err());
+            assert!(opa.policy_history(policy_id).await.is_err());
+        }
     }
 }
@@ -0,0 +1,150 @@
+//! Rego backend using [regorus](https://github.com/microsoft/regorus), a Rego
+//! interpreter written in Rust. It needs no Go toolchain to build and no data
+//! is passed across FFI.
+
+use anyhow::{anyhow, bail, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::Rego;
+
+/// The query of the policy decision.
+const POLICY_QUERY: &str = "data.policy";
+
+/// Bumped on each eviction, s.t. the caches of all the threads are dropped.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// The parsed policies by the policy id, together with their digests.
+#[derive(Default)]
+struct EngineCache {
+    generation: u64,
+    engines: HashMap<String, (String, regorus::Engine)>,
+}
+
+thread_local! {
+    // Each thread keeps its own parsed policies, s.t. concurrent evaluations
+    // do not wait for each other.
+    static ENGINES: RefCell<EngineCache> = RefCell::new(EngineCache::default());
+}
+
+/// The parsed policies are cached under the policy id and the digest of the
+/// policy content, and each evaluation works on a copy of the parsed policy.
+#[derive(Debug, Default)]
+pub struct Regorus;
+
+impl Regorus {
+    fn engine(policy: &str) -> Result<regorus::Engine> {
+        let mut engine = regorus::Engine::new();
+        engine
+            .add_policy("policy.rego".to_string(), policy.to_string())
+            .map_err(|e| anyhow!("Invalid OPA policy: {e}"))?;
+
+        Ok(engine)
+    }
+
+    /// Get a copy of the parsed policy from the cache, or parse and cache it if
+    /// the policy is not cached or its content has changed. An empty
+    /// `policy_id` parses the policy without caching it.
+    fn cached_engine(policy_id: &str, digest: &str, policy: &str) -> Result<regorus::Engine> {
+        if policy_id.is_empty() {
+            return Self::engine(policy);
+        }
+
+        let generation = GENERATION.load(Ordering::Acquire);
+        ENGINES.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if cache.generation != generation {
+                cache.engines.clear();
+                cache.generation = generation;
+            }
+
+            if let Some((cached_digest, engine)) = cache.engines.get(policy_id) {
+                if cached_digest == digest {
+                    return Ok(engine.clone());
+                }
+            }
+
+            let engine = Self::engine(policy)?;
+            cache
+                .engines
+                .insert(policy_id.to_string(), (digest.to_string(), engine.clone()));
+
+            Ok(engine)
+        })
+    }
+
+    /// Evaluate `data.policy`, which must be an object of the policy rules.
+    fn eval(engine: &mut regorus::Engine) -> Result<serde_json::Value> {
+        let results = engine.eval_query(POLICY_QUERY.to_string(), false)?;
+        let decision = results
+            .result
+            .first()
+            .and_then(|result| result.expressions.first())
+            .map(|expression| serde_json::to_value(&expression.value))
+            .transpose()?
+            .filter(serde_json::Value::is_object)
+            .ok_or_else(|| anyhow!("Policy must be in `package policy`"))?;
+
+        Ok(decision)
+    }
+}
+
+impl Rego for Regorus {
+    fn compile(&self, policy: &str) -> Result<()> {
+        // Evaluate the policy without input, s.t. errors which are not found
+        // by the parser, e.g. unknown functions, are reported as well.
+        let mut engine = Self::engine(policy)?;
+        if let Err(e) = Self::eval(&mut engine) {
+            bail!("Invalid OPA policy: {e}");
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(
+        &self,
+        policy_id: &str,
+        digest: &str,
+        policy: &str,
+        data: &str,
+        input: &str,
+    ) -> Result<String> {
+        let mut engine = Self::cached_engine(policy_id, digest, policy)?;
+        engine.add_data(regorus::Value::from_json_str(data)?)?;
+        engine.set_input(regorus::Value::from_json_str(input)?);
+
+        Ok(Self::eval(&mut engine)?.to_string())
+    }
+
+    fn evict(&self, _policy_id: &str) {
+        GENERATION.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_evaluate_cached() {
+        let regorus = Regorus;
+        let data = json!({ "reference": {} }).to_string();
+        let input = json!({}).to_string();
+        let v1 = "package policy\nversion := 1";
+        let v2 = "package policy\nversion := 2";
+
+        let decision = regorus.evaluate("test", "v1", v1, &data, &input).unwrap();
+        assert_eq!(decision, json!({"version": 1}).to_string());
+
+        // The policy is parsed again once its digest has changed.
+        let decision = regorus.evaluate("test", "v2", v2, &data, &input).unwrap();
+        assert_eq!(decision, json!({"version": 2}).to_string());
+
+        // The evicted policy is parsed again even if its digest is unchanged.
+        regorus.evict("test");
+        let decision = regorus.evaluate("test", "v2", v1, &data, &input).unwrap();
+        assert_eq!(decision, json!({"version": 1}).to_string());
+    }
+}
@@ -27,6 +27,7 @@ extern "C" {
         linkDir: GoString,
         lineNormalizationc: i32,
     ) -> *mut c_char;
+    pub fn freeGo(s: *mut c_char);
 }
 
 pub fn verify(
@@ -93,7 +94,9 @@ pub fn verify(
     };
 
     let result_str: &CStr = unsafe { CStr::from_ptr(result_buf) };
-    let res = result_str.to_str()?.to_string();
+    let res = result_str.to_str().map(str::to_string);
+    unsafe { freeGo(result_buf) };
+    let res = res?;
 
     if res.starts_with("Error::") {
         bail!(res);
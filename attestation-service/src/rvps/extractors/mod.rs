@@ -10,6 +10,7 @@ pub mod extractor_modules;
 use anyhow::*;
 
 use std::collections::HashMap;
+use std::sync::RwLock;
 
 use self::extractor_modules::{ExtractorInstance, ExtractorModuleList};
 
@@ -31,7 +32,7 @@ pub trait Extractors {
     /// succeeds, return the generated ReferenceValue.
     /// All the digests value inside a ReferenceValue must be
     /// base64 encoded.
-    fn process(&mut self, message: Message) -> Result<Vec<ReferenceValue>>;
+    fn process(&self, message: Message) -> Result<Vec<ReferenceValue>>;
 }
 
 /// The struct `ExtractorsImpl` is responsible for implementing
@@ -44,37 +45,53 @@ pub trait Extractors {
 #[derive(Default)]
 pub struct ExtractorsImpl {
     extractors_module_list: ExtractorModuleList,
-    extractors_instance_map: HashMap<String, ExtractorInstance>,
+    extractors_instance_map: RwLock<HashMap<String, ExtractorInstance>>,
 }
 
 impl ExtractorsImpl {
     /// Register an `Extractor` instance to `Extractors`. The `Extractor` is responsible for
     /// handling specific kind of provenance (as `extractor_name` indicates).
-    fn register_instance(&mut self, extractor_name: String, extractor_instance: ExtractorInstance) {
+    /// An instance registered concurrently under the same name is kept.
+    fn register_instance(
+        &self,
+        extractor_name: String,
+        extractor_instance: ExtractorInstance,
+    ) -> Result<()> {
         self.extractors_instance_map
-            .insert(extractor_name, extractor_instance);
+            .write()
+            .map_err(|_| anyhow!("Extractors lock poisoned"))?
+            .entry(extractor_name)
+            .or_insert(extractor_instance);
+        Ok(())
     }
 
     /// Instantiate an `Extractor` of given type `extractor_name`. This method will
     /// instantiate an `Extractor` instance and then register it.
-    fn instantiate_extractor(&mut self, extractor_name: String) -> Result<()> {
+    fn instantiate_extractor(&self, extractor_name: String) -> Result<()> {
         let instantiate_func = self.extractors_module_list.get_func(&extractor_name)?;
         let extractor_instance = (instantiate_func)();
-        self.register_instance(extractor_name, extractor_instance);
-        Ok(())
+        self.register_instance(extractor_name, extractor_instance)
     }
 }
 
 impl Extractors for ExtractorsImpl {
-    fn process(&mut self, message: Message) -> Result<Vec<ReferenceValue>> {
+    fn process(&self, message: Message) -> Result<Vec<ReferenceValue>> {
         let typ = message.r#type;
 
-        if self.extractors_instance_map.get_mut(&typ).is_none() {
+        let instantiated = self
+            .extractors_instance_map
+            .read()
+            .map_err(|_| anyhow!("Extractors lock poisoned"))?
+            .contains_key(&typ);
+        if !instantiated {
             self.instantiate_extractor(typ.clone())?;
         }
-        let extractor_instance = self
+        let extractors_instance_map = self
             .extractors_instance_map
-            .get_mut(&typ)
+            .read()
+            .map_err(|_| anyhow!("Extractors lock poisoned"))?;
+        let extractor_instance = extractors_instance_map
+            .get(&typ)
             .ok_or_else(|| anyhow!("The Extractor instance does not existing!"))?;
 
         extractor_instance.verify_and_extract(&message.payload)
@@ -4,11 +4,11 @@
 //
 
 use anyhow::*;
-use tokio::sync::Mutex;
+use std::collections::HashMap;
 
 use self::rvps_api::{
     reference_value_provider_service_client::ReferenceValueProviderServiceClient,
-    ReferenceValueQueryRequest, ReferenceValueRegisterRequest,
+    ReferenceValueQueryRequest, ReferenceValueRegisterRequest, ReferenceValuesQueryRequest,
 };
 
 use super::{Message, TrustedDigest, RVPSAPI};
@@ -17,30 +17,29 @@ pub mod rvps_api {
     tonic::include_proto!("reference");
 }
 
-/// An agent for rvps, uses grpc to connect
+/// An agent for rvps, uses grpc to connect. The underlying channel is
+/// multiplexed, s.t. each request works on a cheap clone of the client and
+/// concurrent requests do not wait for each other.
 pub struct Agent {
-    client: Mutex<ReferenceValueProviderServiceClient<tonic::transport::Channel>>,
+    client: ReferenceValueProviderServiceClient<tonic::transport::Channel>,
 }
 
 impl Agent {
     pub async fn new(addr: &str) -> Result<Self> {
         Ok(Self {
-            client: Mutex::new(
-                ReferenceValueProviderServiceClient::connect(addr.to_string()).await?,
-            ),
+            client: ReferenceValueProviderServiceClient::connect(addr.to_string()).await?,
         })
     }
 }
 
 #[async_trait::async_trait]
 impl RVPSAPI for Agent {
-    async fn verify_and_extract(&mut self, message: Message) -> Result<()> {
+    async fn verify_and_extract(&self, message: Message) -> Result<()> {
         let message = serde_json::to_string(&message)?;
         let req = tonic::Request::new(ReferenceValueRegisterRequest { message });
         let _ = self
             .client
-            .lock()
-            .await
+            .clone()
             .register_reference_value(req)
             .await
             .context("register failed")?;
@@ -53,12 +52,25 @@ impl RVPSAPI for Agent {
         });
         let res = self
             .client
-            .lock()
-            .await
+            .clone()
             .query_reference_value(req)
             .await?
             .into_inner();
         let trust_digest = serde_json::from_str(&res.reference_value_results)?;
         Ok(trust_digest)
     }
+
+    async fn get_digests_batch(&self, names: &[&str]) -> Result<HashMap<String, TrustedDigest>> {
+        let req = tonic::Request::new(ReferenceValuesQueryRequest {
+            names: names.iter().map(|name| name.to_string()).collect(),
+        });
+        let res = self
+            .client
+            .clone()
+            .query_reference_values(req)
+            .await?
+            .into_inner();
+        let trust_digests = serde_json::from_str(&res.reference_value_results)?;
+        Ok(trust_digests)
+    }
 }
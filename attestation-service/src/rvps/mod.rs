@@ -23,6 +23,7 @@ pub use native::Core;
 
 use anyhow::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 pub use reference_value::{ReferenceValue, TrustedDigest};
 pub use store::Store;
@@ -51,12 +52,26 @@ fn default_version() -> String {
     MESSAGE_VERSION.into()
 }
 
-/// The interfaces of Reference Value Provider Service
+/// The interfaces of Reference Value Provider Service. All of them take
+/// `&self`, s.t. an implementation can be shared and called concurrently.
 /// * `verify_and_extract` is responsible for verify a message and
 /// store reference values from it.
 /// * `get_digests` gets trusted digests by the artifact's name.
+/// * `get_digests_batch` gets trusted digests of several artifacts at once.
+/// Artifacts without trusted digests are omitted from the result.
 #[async_trait::async_trait]
 pub trait RVPSAPI {
-    async fn verify_and_extract(&mut self, message: Message) -> Result<()>;
+    async fn verify_and_extract(&self, message: Message) -> Result<()>;
     async fn get_digests(&self, name: &str) -> Result<Option<TrustedDigest>>;
+
+    async fn get_digests_batch(&self, names: &[&str]) -> Result<HashMap<String, TrustedDigest>> {
+        let mut digests = HashMap::new();
+        for name in names {
+            if let Some(digest) = self.get_digests(name).await? {
+                digests.insert(name.to_string(), digest);
+            }
+        }
+
+        Ok(digests)
+    }
 }
@@ -44,7 +44,7 @@ impl Core {
 
 #[async_trait::async_trait]
 impl RVPSAPI for Core {
-    async fn verify_and_extract(&mut self, mut message: Message) -> Result<()> {
+    async fn verify_and_extract(&self, mut message: Message) -> Result<()> {
         // Judge the version field
         if message.version != MESSAGE_VERSION {
             bail!(
@@ -42,7 +42,7 @@ impl LocalFs {
 }
 
 impl Store for LocalFs {
-    fn set(&mut self, name: String, rv: ReferenceValue) -> Result<Option<ReferenceValue>> {
+    fn set(&self, name: String, rv: ReferenceValue) -> Result<Option<ReferenceValue>> {
         let rv_serde = serde_json::to_vec(&rv)?;
         let res = match self
             .engine
@@ -86,7 +86,7 @@ mod tests {
     fn set_and_get() {
         let temp_dir = tempfile::tempdir().expect("create tempdir failed");
         {
-            let mut store = LocalFs::new(temp_dir.path()).expect("create local fs store failed.");
+            let store = LocalFs::new(temp_dir.path()).expect("create local fs store failed.");
             let rv = ReferenceValue::new().expect("create ReferenceValue failed.");
             assert!(
                 store
@@ -111,7 +111,7 @@ mod tests {
     fn set_duplicated() {
         let temp_dir = tempfile::tempdir().expect("create tempdir failed");
         {
-            let mut store = LocalFs::new(temp_dir.path()).expect("create local fs store failed.");
+            let store = LocalFs::new(temp_dir.path()).expect("create local fs store failed.");
             let rv_old = ReferenceValue::new()
                 .expect("create ReferenceValue failed.")
                 .set_name("old");
@@ -146,7 +146,7 @@ mod tests {
         let rv = ReferenceValue::new().expect("create ReferenceValue failed.");
         let temp_dir = tempfile::tempdir().expect("create tempdir failed");
         {
-            let mut store = LocalFs::new(temp_dir.path()).expect("create local fs store failed.");
+            let store = LocalFs::new(temp_dir.path()).expect("create local fs store failed.");
             store
                 .set(KEY.to_owned(), rv.clone())
                 .expect("set rv failed.");
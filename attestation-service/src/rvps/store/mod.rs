@@ -35,8 +35,9 @@ impl StoreType {
 /// forms will have the same interface as following.
 pub trait Store {
     /// Store a reference value. If the given `name` exists,
-    /// return the previous `Some<ReferenceValue>`, otherwise return `None`
-    fn set(&mut self, name: String, rv: ReferenceValue) -> Result<Option<ReferenceValue>>;
+    /// return the previous `Some<ReferenceValue>`, otherwise return `None`.
+    /// The store must be safe to be set and read concurrently.
+    fn set(&self, name: String, rv: ReferenceValue) -> Result<Option<ReferenceValue>>;
 
     // Retrieve a reference value
     fn get(&self, name: &str) -> Result<Option<ReferenceValue>>;
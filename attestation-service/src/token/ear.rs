@@ -0,0 +1,351 @@
+// Copyright (c) 2023 by Alibaba.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A token broker which issues attestation results in the
+//! [EAT Attestation Result (EAR)](https://datatracker.ietf.org/doc/draft-fv-rats-ear/)
+//! format. The claims follow the EAR draft, with the JWT claims of the AS
+//! (`iss`, `exp`, `jwk`, ...) on top of them.
+
+use anyhow::*;
+use serde_json::{json, Map, Value};
+
+use crate::token::jwe;
+use crate::token::key::SharedKeyRing;
+use crate::token::{AttestationTokenBroker, AttestationTokenConfig};
+
+const EAR_PROFILE: &str = "tag:github.com,2023:veraison/ear";
+const VERIFIER_DEVELOPER: &str = "https://confidential-containers.org";
+const DEFAULT_SUBMODULE: &str = "cpu";
+
+/// The custom claims which are reported in the EAR appraisal of the submodule,
+/// instead of as top-level claims.
+const APPRAISAL_CLAIMS: [&str; 4] = ["tee", "tcb-status", "allow", "evaluation-reports"];
+
+/// The claims of an AR4SI trustworthiness vector.
+const TRUST_VECTOR_CLAIMS: [&str; 8] = [
+    "instance-identity",
+    "configuration",
+    "executables",
+    "file-system",
+    "hardware",
+    "runtime-opaque",
+    "storage-opaque",
+    "sourced-data",
+];
+
+/// Trustworthiness claim values used when the policy does not give its own.
+const TRUST_CLAIM_AFFIRMING: i64 = 2;
+const TRUST_CLAIM_CONTRAINDICATED: i64 = 96;
+
+pub struct EarAttestationTokenBroker {
+    keys: SharedKeyRing,
+    config: AttestationTokenConfig,
+}
+
+impl EarAttestationTokenBroker {
+    pub fn new(config: AttestationTokenConfig) -> Result<Self> {
+        let keys = SharedKeyRing::new(config.clone())?;
+
+        Ok(Self { keys, config })
+    }
+}
+
+/// Get the policy id and output of each evaluated policy from the
+/// `evaluation-reports` claim. The outputs may be JSON encoded strings.
+fn evaluation_reports(custom_claims: &Map<String, Value>) -> Vec<(String, Value)> {
+    let Some(Value::Array(reports)) = custom_claims.get("evaluation-reports") else {
+        return Vec::new();
+    };
+
+    reports
+        .iter()
+        .map(|report| {
+            let policy_id = report["policy-id"].as_str().unwrap_or_default().to_string();
+            let output = match &report["evaluation-report"] {
+                Value::String(output) => serde_json::from_str(output).unwrap_or(Value::Null),
+                output => output.clone(),
+            };
+            (policy_id, output)
+        })
+        .collect()
+}
+
+/// Build the trustworthiness vector of a submodule.
+///
+/// The evidence has been verified by the verifier when we get here, so the
+/// `instance-identity` and `hardware` claims are affirming. `configuration`
+/// and `executables` follow the `allow` decisions of the policies. A policy can
+/// set any claim itself with a `trust_vector` rule, e.g.
+/// `trust_vector := {"executables": 3}`. If a claim is also derived or set by
+/// another policy, the least trustworthy value wins, s.t. a policy cannot
+/// make the evidence look more trustworthy.
+fn trust_vector(outputs: &[&Value]) -> Map<String, Value> {
+    let mut vector = Map::new();
+    vector.insert("instance-identity".into(), json!(TRUST_CLAIM_AFFIRMING));
+    vector.insert("hardware".into(), json!(TRUST_CLAIM_AFFIRMING));
+
+    let denied = outputs
+        .iter()
+        .any(|output| output["allow"].as_bool() == Some(false));
+    let policy_claim = match denied {
+        true => TRUST_CLAIM_CONTRAINDICATED,
+        false => TRUST_CLAIM_AFFIRMING,
+    };
+    vector.insert("configuration".into(), json!(policy_claim));
+    vector.insert("executables".into(), json!(policy_claim));
+
+    for output in outputs {
+        let Some(claims) = output["trust_vector"].as_object() else {
+            continue;
+        };
+        for (claim, value) in claims {
+            let Some(value) = value
+                .as_i64()
+                .filter(|_| TRUST_VECTOR_CLAIMS.contains(&claim.as_str()))
+            else {
+                warn!("Ignore illegal trustworthiness claim {claim}: {value}");
+                continue;
+            };
+            let worse = match vector.get(claim).and_then(Value::as_i64) {
+                Some(current) => rank(value) > rank(current),
+                None => true,
+            };
+            if worse {
+                vector.insert(claim.clone(), json!(value));
+            }
+        }
+    }
+
+    vector
+}
+
+/// The trust tiers from the most to the least trustworthy.
+const TIERS: [&str; 4] = ["none", "affirming", "warning", "contraindicated"];
+
+/// Get the trust tier of a trustworthiness claim value.
+fn tier(value: i64) -> &'static str {
+    match value {
+        0..=1 => "none",
+        2..=31 => "affirming",
+        32..=95 => "warning",
+        _ => "contraindicated",
+    }
+}
+
+/// Order the claim values from the most to the least trustworthy.
+fn rank(value: i64) -> (usize, i64) {
+    let tier = TIERS.iter().position(|t| *t == tier(value)).unwrap_or(0);
+    (tier, value)
+}
+
+/// Whether a submodule of this tier is accepted, i.e. its evidence is not
+/// denied. A warning does not deny the evidence.
+fn accepted(tier: &str) -> bool {
+    matches!(tier, "affirming" | "warning")
+}
+
+/// The status of a submodule is the worst tier of its trustworthiness vector.
+fn status(vector: &Map<String, Value>) -> &'static str {
+    vector
+        .values()
+        .filter_map(Value::as_i64)
+        .max_by_key(|value| rank(*value))
+        .map(tier)
+        .unwrap_or("none")
+}
+
+impl AttestationTokenBroker for EarAttestationTokenBroker {
+    fn issue(&self, custom_claims: Value) -> Result<String> {
+        let custom_claims = custom_claims
+            .as_object()
+            .ok_or_else(|| anyhow!("Illegal token custom claims"))?;
+
+        let submodule = custom_claims
+            .get("tee")
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_SUBMODULE);
+        let reports = evaluation_reports(custom_claims);
+        let outputs: Vec<&Value> = reports.iter().map(|(_, output)| output).collect();
+        let vector = trust_vector(&outputs);
+        let policy_ids: Vec<&str> = reports.iter().map(|(id, _)| id.as_str()).collect();
+        let policy_claims: Map<String, Value> = reports.iter().cloned().collect();
+
+        let appraisal = json!({
+            "ear.status": status(&vector),
+            "ear.trustworthiness-vector": vector,
+            "ear.appraisal-policy-id": policy_ids.join(","),
+            "ear.veraison.annotated-evidence": custom_claims.get("tcb-status").cloned().unwrap_or_default(),
+            "ear.veraison.policy-claims": policy_claims,
+        });
+
+        let keys = self.keys.read()?;
+        let key = keys.active();
+
+        let now = time::OffsetDateTime::now_utc();
+        let exp = now + time::Duration::minutes(self.config.duration_min);
+
+        let mut claims = json!({
+            "eat_profile": EAR_PROFILE,
+            "iat": now.unix_timestamp(),
+            "ear.verifier-id": {
+                "developer": VERIFIER_DEVELOPER,
+                "build": format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            },
+            "submods": {
+                submodule: appraisal,
+            },
+            "iss": self.config.issuer(),
+            "jti": uuid::Uuid::new_v4().to_string(),
+            "jwk": key.jwk()?,
+            "nbf": now.unix_timestamp(),
+            "exp": exp.unix_timestamp(),
+            "tee-pubkey": custom_claims.get("tee-pubkey").cloned().unwrap_or_default(),
+        });
+        // Other custom claims, e.g. `aud`, `sub` and the claims derived by the
+        // policies, are added as top-level claims.
+        for (name, value) in custom_claims {
+            if !APPRAISAL_CLAIMS.contains(&name.as_str()) && claims.get(name).is_none() {
+                claims[name] = value.clone();
+            }
+        }
+
+        let token = key.sign_jwt(&claims)?;
+        if self.config.encrypt_token {
+            return jwe::encrypt(&token, &claims["tee-pubkey"]);
+        }
+
+        Ok(token)
+    }
+
+    fn pubkey_jwks(&self) -> Result<String> {
+        self.keys.jwks()
+    }
+
+    fn rotate_key(&self) -> Result<()> {
+        self.keys.rotate()
+    }
+
+    fn verify(&self, token: &str, audience: Option<&str>) -> Result<Value> {
+        let claims = self
+            .keys
+            .verify_jwt(token, self.config.issuer(), audience)?;
+        let submods = claims["submods"]
+            .as_object()
+            .filter(|submods| !submods.is_empty())
+            .ok_or_else(|| anyhow!("Token has no submodule"))?;
+        if submods
+            .values()
+            .any(|appraisal| !accepted(appraisal["ear.status"].as_str().unwrap_or_default()))
+        {
+            bail!("Token reports denied evidence");
+        }
+
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    fn claims(token: &str) -> Value {
+        let claims = URL_SAFE_NO_PAD
+            .decode(token.split('.').nth(1).unwrap())
+            .unwrap();
+        serde_json::from_slice(&claims).unwrap()
+    }
+
+    #[test]
+    fn test_issue_affirming() {
+        let broker = EarAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        let token = broker
+            .issue(json!({
+                "tee": "tdx",
+                "tcb-status": {"tdx.quote.body.mr_td": "1234"},
+                "tier": "gold",
+                "evaluation-reports": [{
+                    "policy-id": "default",
+                    "evaluation-report": r#"{"allow":true}"#,
+                }],
+            }))
+            .unwrap();
+
+        let claims = claims(&token);
+        assert_eq!(claims["eat_profile"], EAR_PROFILE);
+        assert_eq!(claims["tier"], "gold");
+        assert!(claims.get("tcb-status").is_none());
+        let appraisal = &claims["submods"]["tdx"];
+        assert_eq!(appraisal["ear.status"], "affirming");
+        assert_eq!(
+            appraisal["ear.trustworthiness-vector"]["executables"],
+            TRUST_CLAIM_AFFIRMING
+        );
+        assert_eq!(
+            appraisal["ear.veraison.annotated-evidence"]["tdx.quote.body.mr_td"],
+            "1234"
+        );
+        assert_eq!(appraisal["ear.appraisal-policy-id"], "default");
+        assert_eq!(
+            appraisal["ear.veraison.policy-claims"]["default"]["allow"],
+            true
+        );
+    }
+
+    #[test]
+    fn test_verify_denied() {
+        let broker = EarAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        let issue = |allow: bool| {
+            broker
+                .issue(json!({
+                    "tee": "tdx",
+                    "evaluation-reports": [{
+                        "policy-id": "default",
+                        "evaluation-report": {"allow": allow},
+                    }],
+                }))
+                .unwrap()
+        };
+
+        assert!(broker.verify(&issue(true), None).is_ok());
+        assert!(broker.verify(&issue(false), None).is_err());
+    }
+
+    #[test]
+    fn test_policy_trust_vector() {
+        let report = json!({
+            "allow": true,
+            "trust_vector": {
+                "file-system": 33,
+                "unknown-claim": 2,
+            },
+        });
+
+        let vector = trust_vector(&[&report]);
+        assert_eq!(vector["file-system"], 33);
+        assert!(vector.get("unknown-claim").is_none());
+        assert_eq!(status(&vector), "warning");
+
+        let vector = trust_vector(&[&report, &json!({"allow": false})]);
+        assert_eq!(status(&vector), "contraindicated");
+    }
+
+    #[test]
+    fn test_worst_policy_claim_wins() {
+        let first = json!({"trust_vector": {"executables": 3}});
+        let second = json!({"trust_vector": {"executables": 33}});
+
+        let vector = trust_vector(&[&first, &second]);
+        assert_eq!(vector["executables"], 33);
+        let vector = trust_vector(&[&second, &first]);
+        assert_eq!(vector["executables"], 33);
+
+        // A denying policy cannot affirm the claims derived from its decision.
+        let denying = json!({"allow": false, "trust_vector": {"executables": 2}});
+        let vector = trust_vector(&[&denying]);
+        assert_eq!(vector["executables"], TRUST_CLAIM_CONTRAINDICATED);
+        assert_eq!(status(&vector), "contraindicated");
+    }
+}
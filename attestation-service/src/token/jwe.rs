@@ -0,0 +1,129 @@
+// Copyright (c) 2023 by Alibaba.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encrypt the attestation token to the TEE public key as a
+//! [JSON Web Encryption](https://www.rfc-editor.org/rfc/rfc7516), s.t.
+//! only the attested TEE can read the token body.
+
+use anyhow::*;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use openssl::bn::BigNum;
+use openssl::rand::rand_bytes;
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{encrypt_aead, Cipher};
+use serde_json::{json, Value};
+
+const JWE_ALG: &str = "RSA-OAEP";
+const JWE_ENC: &str = "A256GCM";
+const CEK_LEN: usize = 32;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Wrap the signed token into a JWE in compact serialization, encrypted with
+/// `RSA-OAEP` + `A256GCM` to `tee_pubkey`, which is the JWK-formatted RSA key
+/// given in the `tee-pubkey` of the attestation.
+pub fn encrypt(token: &str, tee_pubkey: &Value) -> Result<String> {
+    if let Some(kty) = tee_pubkey["kty"].as_str() {
+        if kty != "RSA" {
+            bail!("JWE only supports RSA TEE public key, got {kty}");
+        }
+    }
+    let n = tee_pubkey["n"]
+        .as_str()
+        .ok_or_else(|| anyhow!("TEE public key has no modulus"))?;
+    let e = tee_pubkey["e"]
+        .as_str()
+        .ok_or_else(|| anyhow!("TEE public key has no exponent"))?;
+    let rsa = Rsa::from_public_components(
+        BigNum::from_slice(&URL_SAFE_NO_PAD.decode(n)?)?,
+        BigNum::from_slice(&URL_SAFE_NO_PAD.decode(e)?)?,
+    )?;
+
+    let header_value = json!({
+        "alg": JWE_ALG,
+        "enc": JWE_ENC,
+        "cty": "JWT",
+    });
+    let header_b64 = URL_SAFE_NO_PAD.encode(serde_json::to_string(&header_value)?);
+
+    let mut cek = [0u8; CEK_LEN];
+    rand_bytes(&mut cek)?;
+    let mut iv = [0u8; IV_LEN];
+    rand_bytes(&mut iv)?;
+
+    let mut encrypted_key = vec![0u8; rsa.size() as usize];
+    let len = rsa.public_encrypt(&cek, &mut encrypted_key, Padding::PKCS1_OAEP)?;
+    encrypted_key.truncate(len);
+
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &cek,
+        Some(&iv),
+        header_b64.as_bytes(),
+        token.as_bytes(),
+        &mut tag,
+    )?;
+
+    Ok(format!(
+        "{header_b64}.{}.{}.{}.{}",
+        URL_SAFE_NO_PAD.encode(encrypted_key),
+        URL_SAFE_NO_PAD.encode(iv),
+        URL_SAFE_NO_PAD.encode(ciphertext),
+        URL_SAFE_NO_PAD.encode(tag),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::symm::decrypt_aead;
+
+    #[test]
+    fn test_encrypt() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let tee_pubkey = json!({
+            "kty": "RSA",
+            "alg": "RSA1_5",
+            "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+            "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+        });
+
+        let jwe = encrypt("header.claims.signature", &tee_pubkey).unwrap();
+        let parts: Vec<Vec<u8>> = jwe
+            .split('.')
+            .map(|part| URL_SAFE_NO_PAD.decode(part).unwrap())
+            .collect();
+        assert_eq!(parts.len(), 5);
+
+        let header: Value = serde_json::from_slice(&parts[0]).unwrap();
+        assert_eq!(header["alg"], JWE_ALG);
+        assert_eq!(header["enc"], JWE_ENC);
+
+        let mut cek = vec![0u8; rsa.size() as usize];
+        let len = rsa
+            .private_decrypt(&parts[1], &mut cek, Padding::PKCS1_OAEP)
+            .unwrap();
+        let plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &cek[..len],
+            Some(&parts[2]),
+            jwe.split('.').next().unwrap().as_bytes(),
+            &parts[3],
+            &parts[4],
+        )
+        .unwrap();
+        assert_eq!(plaintext, b"header.claims.signature");
+    }
+
+    #[test]
+    fn test_encrypt_non_rsa_key() {
+        let tee_pubkey = json!({
+            "kty": "EC",
+            "crv": "P-256",
+        });
+        assert!(encrypt("token", &tee_pubkey).is_err());
+    }
+}
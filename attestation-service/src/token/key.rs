@@ -0,0 +1,805 @@
+// Copyright (c) 2023 by Alibaba.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signing keys of the attestation token brokers.
+
+use anyhow::*;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::{RsaPssSaltlen, Signer, Verifier};
+use openssl::x509::X509;
+use serde_json::{json, Value};
+use std::sync::{RwLock, RwLockReadGuard};
+use time::{Duration, OffsetDateTime};
+
+use crate::token::{AttestationTokenConfig, TokenAlgorithm, TokenSignerConfig};
+
+const RSA_KEY_BITS: u32 = 2048;
+
+/// A key used to sign attestation tokens.
+pub struct SigningKey {
+    kid: String,
+    alg: TokenAlgorithm,
+    private_key: PKey<Private>,
+    cert_chain: Option<Vec<X509>>,
+    created: OffsetDateTime,
+}
+
+impl SigningKey {
+    /// Load the signing key and the optional certificate chain from PEM files.
+    /// If `alg` is not given, it is derived from the type of the key.
+    pub fn load(signer: &TokenSignerConfig, alg: Option<TokenAlgorithm>) -> Result<Self> {
+        let pem = std::fs::read(&signer.key_path)
+            .map_err(|e| anyhow!("Read token signing key {} failed: {e}", signer.key_path))?;
+        let private_key = PKey::private_key_from_pem(&pem)
+            .map_err(|e| anyhow!("Parse token signing key failed: {e}"))?;
+
+        let cert_chain = match &signer.cert_path {
+            Some(cert_path) => {
+                let pem = std::fs::read(cert_path)
+                    .map_err(|e| anyhow!("Read token certificate chain {cert_path} failed: {e}"))?;
+                let cert_chain = X509::stack_from_pem(&pem)
+                    .map_err(|e| anyhow!("Parse token certificate chain failed: {e}"))?;
+                let leaf = cert_chain
+                    .first()
+                    .ok_or_else(|| anyhow!("Token certificate chain {cert_path} is empty"))?;
+                if !leaf.public_key()?.public_eq(&private_key) {
+                    bail!("Token certificate does not match the signing key");
+                }
+                Some(cert_chain)
+            }
+            None => None,
+        };
+
+        let key_alg = default_alg(&private_key)?;
+        let alg = match alg {
+            Some(alg) => {
+                let compatible = match alg {
+                    TokenAlgorithm::RS384 | TokenAlgorithm::PS384 => {
+                        key_alg == TokenAlgorithm::RS384
+                    }
+                    _ => key_alg == alg,
+                };
+                if !compatible {
+                    bail!(
+                        "Token signing key cannot be used with algorithm {}",
+                        alg.as_str()
+                    );
+                }
+                alg
+            }
+            None => key_alg,
+        };
+
+        Self::from_parts(alg, private_key, cert_chain)
+    }
+
+    /// Generate a new ephemeral signing key for the algorithm.
+    pub fn generate(alg: TokenAlgorithm) -> Result<Self> {
+        let private_key = match alg {
+            TokenAlgorithm::RS384 | TokenAlgorithm::PS384 => {
+                PKey::from_rsa(Rsa::generate(RSA_KEY_BITS)?)?
+            }
+            TokenAlgorithm::ES256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+            TokenAlgorithm::ES384 => {
+                let group = EcGroup::from_curve_name(Nid::SECP384R1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+            TokenAlgorithm::EdDSA => PKey::generate_ed25519()?,
+        };
+
+        Self::from_parts(alg, private_key, None)
+    }
+
+    fn from_parts(
+        alg: TokenAlgorithm,
+        private_key: PKey<Private>,
+        cert_chain: Option<Vec<X509>>,
+    ) -> Result<Self> {
+        let mut key = Self {
+            kid: String::new(),
+            alg,
+            private_key,
+            cert_chain,
+            created: OffsetDateTime::now_utc(),
+        };
+        key.kid = key.thumbprint()?;
+        Ok(key)
+    }
+
+    /// The key id, which is the [JWK thumbprint](https://www.rfc-editor.org/rfc/rfc7638)
+    /// of the public key.
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    /// Get the JWS algorithm name of the signing key.
+    pub fn alg(&self) -> &'static str {
+        self.alg.as_str()
+    }
+
+    pub fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        match self.alg {
+            TokenAlgorithm::RS384 => {
+                let mut signer = Signer::new(MessageDigest::sha384(), &self.private_key)?;
+                signer.update(payload)?;
+                Ok(signer.sign_to_vec()?)
+            }
+            TokenAlgorithm::PS384 => {
+                let mut signer = Signer::new(MessageDigest::sha384(), &self.private_key)?;
+                signer.set_rsa_padding(Padding::PKCS1_PSS)?;
+                signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                signer.update(payload)?;
+                Ok(signer.sign_to_vec()?)
+            }
+            TokenAlgorithm::ES256 => {
+                ecdsa_sign(&self.private_key, MessageDigest::sha256(), 32, payload)
+            }
+            TokenAlgorithm::ES384 => {
+                ecdsa_sign(&self.private_key, MessageDigest::sha384(), 48, payload)
+            }
+            TokenAlgorithm::EdDSA => {
+                let mut signer = Signer::new_without_digest(&self.private_key)?;
+                Ok(signer.sign_oneshot_to_vec(payload)?)
+            }
+        }
+    }
+
+    /// Verify a signature created by `sign`.
+    pub fn verify(&self, payload: &[u8], signature: &[u8]) -> Result<bool> {
+        let (digest, len) = match self.alg {
+            TokenAlgorithm::RS384 => {
+                let mut verifier = Verifier::new(MessageDigest::sha384(), &self.private_key)?;
+                verifier.update(payload)?;
+                return Ok(verifier.verify(signature)?);
+            }
+            TokenAlgorithm::PS384 => {
+                let mut verifier = Verifier::new(MessageDigest::sha384(), &self.private_key)?;
+                verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+                verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                verifier.update(payload)?;
+                return Ok(verifier.verify(signature)?);
+            }
+            TokenAlgorithm::EdDSA => {
+                let mut verifier = Verifier::new_without_digest(&self.private_key)?;
+                return Ok(verifier.verify_oneshot(signature, payload)?);
+            }
+            TokenAlgorithm::ES256 => (MessageDigest::sha256(), 32),
+            TokenAlgorithm::ES384 => (MessageDigest::sha384(), 48),
+        };
+
+        if signature.len() != 2 * len {
+            return Ok(false);
+        }
+        let r = BigNum::from_slice(&signature[..len])?;
+        let s = BigNum::from_slice(&signature[len..])?;
+        let der = EcdsaSig::from_private_components(r, s)?.to_der()?;
+
+        let mut verifier = Verifier::new(digest, &self.private_key)?;
+        verifier.update(payload)?;
+        Ok(verifier.verify(&der)?)
+    }
+
+    /// Sign the claims as a JSON Web Token.
+    pub fn sign_jwt(&self, claims: &Value) -> Result<String> {
+        let header_value = json!({
+            "typ": "JWT",
+            "alg": self.alg(),
+            "kid": self.kid,
+        });
+        let header_string = serde_json::to_string(&header_value)?;
+        let header_b64 = URL_SAFE_NO_PAD.encode(header_string.as_bytes());
+
+        let claims_string = serde_json::to_string(claims)?;
+        let claims_b64 = URL_SAFE_NO_PAD.encode(claims_string.as_bytes());
+
+        let signature_payload = format!("{header_b64}.{claims_b64}");
+        let signature = self.sign(signature_payload.as_bytes())?;
+        let signature_b64 = URL_SAFE_NO_PAD.encode(signature);
+
+        Ok(format!("{signature_payload}.{signature_b64}"))
+    }
+
+    /// Get the public key in JWK format, with the `x5c` certificate chain if configured.
+    pub fn jwk(&self) -> Result<Value> {
+        let mut jwk = self.public_jwk_members()?;
+        jwk["alg"] = json!(self.alg());
+        jwk["kid"] = json!(self.kid);
+
+        if let Some(cert_chain) = &self.cert_chain {
+            let x5c = cert_chain
+                .iter()
+                .map(|cert| Ok(STANDARD.encode(cert.to_der()?)))
+                .collect::<Result<Vec<String>>>()?;
+            jwk["x5c"] = json!(x5c);
+        }
+
+        Ok(jwk)
+    }
+
+    /// The required members of the public key JWK.
+    fn public_jwk_members(&self) -> Result<Value> {
+        match self.alg {
+            TokenAlgorithm::RS384 | TokenAlgorithm::PS384 => {
+                let rsa = self.private_key.rsa()?;
+                Ok(json!({
+                    "kty": "RSA",
+                    "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+                    "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+                }))
+            }
+            TokenAlgorithm::ES256 | TokenAlgorithm::ES384 => {
+                let ec = self.private_key.ec_key()?;
+                let (crv, len) = match self.alg {
+                    TokenAlgorithm::ES256 => ("P-256", 32),
+                    _ => ("P-384", 48),
+                };
+                let mut x = BigNum::new()?;
+                let mut y = BigNum::new()?;
+                let mut ctx = BigNumContext::new()?;
+                ec.public_key()
+                    .affine_coordinates(ec.group(), &mut x, &mut y, &mut ctx)?;
+                Ok(json!({
+                    "kty": "EC",
+                    "crv": crv,
+                    "x": URL_SAFE_NO_PAD.encode(x.to_vec_padded(len)?),
+                    "y": URL_SAFE_NO_PAD.encode(y.to_vec_padded(len)?),
+                }))
+            }
+            TokenAlgorithm::EdDSA => Ok(json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": URL_SAFE_NO_PAD.encode(self.private_key.raw_public_key()?),
+            })),
+        }
+    }
+
+    /// Calculate the JWK thumbprint. The members must be serialized in
+    /// lexicographic order, so the JSON string is built by hand.
+    fn thumbprint(&self) -> Result<String> {
+        let jwk = self.public_jwk_members()?;
+        let member = |name: &str| -> Result<String> {
+            jwk[name]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Internal Error: JWK member {name} missing"))
+        };
+        let canonical = match self.alg {
+            TokenAlgorithm::RS384 | TokenAlgorithm::PS384 => format!(
+                r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                member("e")?,
+                member("n")?
+            ),
+            TokenAlgorithm::ES256 | TokenAlgorithm::ES384 => format!(
+                r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                member("crv")?,
+                member("x")?,
+                member("y")?
+            ),
+            TokenAlgorithm::EdDSA => format!(
+                r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#,
+                member("crv")?,
+                member("x")?
+            ),
+        };
+
+        Ok(URL_SAFE_NO_PAD.encode(openssl::sha::sha256(canonical.as_bytes())))
+    }
+}
+
+/// Get the default algorithm for the type of a key.
+fn default_alg(key: &PKey<Private>) -> Result<TokenAlgorithm> {
+    match key.id() {
+        Id::RSA => Ok(TokenAlgorithm::RS384),
+        Id::EC => match key.ec_key()?.group().curve_name() {
+            Some(Nid::X9_62_PRIME256V1) => Ok(TokenAlgorithm::ES256),
+            Some(Nid::SECP384R1) => Ok(TokenAlgorithm::ES384),
+            _ => bail!("Unsupported EC curve of token signing key"),
+        },
+        Id::ED25519 => Ok(TokenAlgorithm::EdDSA),
+        _ => bail!("Unsupported token signing key type"),
+    }
+}
+
+/// Sign with ECDSA and encode the signature as `r || s` required by JWS.
+fn ecdsa_sign(
+    key: &PKey<Private>,
+    digest: MessageDigest,
+    len: i32,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    let mut signer = Signer::new(digest, key)?;
+    signer.update(payload)?;
+    let der = signer.sign_to_vec()?;
+    let sig = EcdsaSig::from_der(&der)?;
+
+    let mut signature = sig.r().to_vec_padded(len)?;
+    signature.extend(sig.s().to_vec_padded(len)?);
+    Ok(signature)
+}
+
+/// The active signing key together with the retired keys whose
+/// tokens may still be valid.
+pub struct KeyRing {
+    active: SigningKey,
+    retired: Vec<(SigningKey, OffsetDateTime)>,
+    config: AttestationTokenConfig,
+}
+
+impl KeyRing {
+    pub fn new(config: AttestationTokenConfig) -> Result<Self> {
+        // A scheduled rotation would only reload the same key from the files.
+        if config.signer.is_some() && config.key_rotation_interval_min.is_some() {
+            bail!("Scheduled rotation of the token signing key from file is not supported");
+        }
+
+        let active = Self::new_key(&config)?;
+
+        Ok(Self {
+            active,
+            retired: Vec::new(),
+            config,
+        })
+    }
+
+    fn new_key(config: &AttestationTokenConfig) -> Result<SigningKey> {
+        match &config.signer {
+            Some(signer) => SigningKey::load(signer, config.algorithm),
+            None => SigningKey::generate(config.algorithm.unwrap_or(TokenAlgorithm::RS384)),
+        }
+    }
+
+    pub fn active(&self) -> &SigningKey {
+        &self.active
+    }
+
+    /// Whether the active key is due to be rotated according to
+    /// `key_rotation_interval_min`.
+    pub fn rotation_due(&self) -> bool {
+        match self.config.key_rotation_interval_min {
+            Some(interval) => {
+                OffsetDateTime::now_utc() >= self.active.created + Duration::minutes(interval)
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the active key with a new one. If a signer is configured,
+    /// the new key is loaded from the configured files, so a key replaced
+    /// on disk is picked up here. The old key is kept in the JWKS until
+    /// the grace period is over.
+    pub fn rotate(&mut self) -> Result<()> {
+        let key = Self::new_key(&self.config)?;
+
+        if key.kid() == self.active.kid() {
+            // The key on disk is unchanged, only restart the rotation interval.
+            self.active.created = key.created;
+            return Ok(());
+        }
+
+        let grace = self
+            .config
+            .retired_key_grace_min
+            .unwrap_or(self.config.duration_min);
+        let expires = OffsetDateTime::now_utc() + Duration::minutes(grace);
+        let old = std::mem::replace(&mut self.active, key);
+        info!(
+            "Token signing key {} retired, new key {}",
+            old.kid(),
+            self.active.kid()
+        );
+        self.retired.push((old, expires));
+        self.prune();
+
+        Ok(())
+    }
+
+    /// Drop the retired keys whose grace period is over.
+    pub fn prune(&mut self) {
+        let now = OffsetDateTime::now_utc();
+        self.retired.retain(|(_, expires)| *expires > now);
+    }
+
+    /// Get all the keys that have not yet expired, active key first.
+    pub fn valid_keys(&self) -> impl Iterator<Item = &SigningKey> {
+        let now = OffsetDateTime::now_utc();
+        std::iter::once(&self.active).chain(
+            self.retired
+                .iter()
+                .filter(move |(_, expires)| *expires > now)
+                .map(|(key, _)| key),
+        )
+    }
+
+    /// Get the JWKS of all the keys that have not yet expired.
+    pub fn jwks(&self) -> Result<String> {
+        let keys = self
+            .valid_keys()
+            .map(SigningKey::jwk)
+            .collect::<Result<Vec<Value>>>()?;
+        let jwks = json!({
+            "keys": keys,
+        });
+
+        Ok(serde_json::to_string(&jwks)?)
+    }
+}
+
+impl KeyRing {
+    /// Verify a JSON Web Token signed by one of the keys that have not yet
+    /// expired, and check its `nbf`, `exp`, `iss` and `aud` claims. A token
+    /// with `aud` is only accepted for one of its audiences, and a token
+    /// without `aud` only if no audience is expected.
+    /// Returns the claims of the token.
+    pub fn verify_jwt(&self, token: &str, issuer: &str, audience: Option<&str>) -> Result<Value> {
+        let mut parts = token.split('.');
+        let (Some(header_b64), Some(claims_b64), Some(signature_b64), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Malformed token");
+        };
+
+        let header: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header_b64)?)
+            .context("Illegal token header")?;
+        let kid = header["kid"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Token header has no kid"))?;
+        let key = self
+            .valid_keys()
+            .find(|key| key.kid() == kid)
+            .ok_or_else(|| anyhow!("Unknown or expired token signing key {kid}"))?;
+        if header["alg"].as_str() != Some(key.alg()) {
+            bail!("Token algorithm does not match the signing key");
+        }
+
+        let signature = URL_SAFE_NO_PAD.decode(signature_b64)?;
+        let signature_payload = format!("{header_b64}.{claims_b64}");
+        if !key.verify(signature_payload.as_bytes(), &signature)? {
+            bail!("Invalid token signature");
+        }
+
+        let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims_b64)?)
+            .context("Illegal token claims")?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let nbf = claims["nbf"]
+            .as_i64()
+            .ok_or_else(|| anyhow!("Token has no nbf"))?;
+        if now < nbf {
+            bail!("Token is not yet valid");
+        }
+        let exp = claims["exp"]
+            .as_i64()
+            .ok_or_else(|| anyhow!("Token has no exp"))?;
+        if now >= exp {
+            bail!("Token has expired");
+        }
+        if claims["iss"].as_str() != Some(issuer) {
+            bail!("Token issuer mismatch");
+        }
+        let audiences: Vec<&str> = match &claims["aud"] {
+            Value::Null => Vec::new(),
+            Value::String(aud) => vec![aud.as_str()],
+            Value::Array(auds) => auds.iter().filter_map(Value::as_str).collect(),
+            _ => bail!("Illegal token audience"),
+        };
+        let audience_matched = match audience {
+            Some(audience) => audiences.contains(&audience),
+            None => audiences.is_empty(),
+        };
+        if !audience_matched {
+            bail!("Token audience mismatch");
+        }
+
+        Ok(claims)
+    }
+}
+
+/// A `KeyRing` shared by the requests of a token broker.
+pub struct SharedKeyRing(RwLock<KeyRing>);
+
+impl SharedKeyRing {
+    pub fn new(config: AttestationTokenConfig) -> Result<Self> {
+        Ok(Self(RwLock::new(KeyRing::new(config)?)))
+    }
+
+    /// Get the key ring to sign a token. The active key is rotated
+    /// first if it is due.
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, KeyRing>> {
+        if self.read_inner()?.rotation_due() {
+            let mut keys = self
+                .0
+                .write()
+                .map_err(|_| anyhow!("Token key ring lock poisoned"))?;
+            // Another request may have rotated the key meanwhile.
+            if keys.rotation_due() {
+                keys.rotate()?;
+            }
+        }
+
+        self.read_inner()
+    }
+
+    fn read_inner(&self) -> Result<RwLockReadGuard<'_, KeyRing>> {
+        self.0
+            .read()
+            .map_err(|_| anyhow!("Token key ring lock poisoned"))
+    }
+
+    pub fn rotate(&self) -> Result<()> {
+        self.0
+            .write()
+            .map_err(|_| anyhow!("Token key ring lock poisoned"))?
+            .rotate()
+    }
+
+    pub fn jwks(&self) -> Result<String> {
+        self.read_inner()?.jwks()
+    }
+
+    pub fn verify_jwt(&self, token: &str, issuer: &str, audience: Option<&str>) -> Result<Value> {
+        self.read_inner()?.verify_jwt(token, issuer, audience)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::x509::X509Name;
+    use rstest::rstest;
+
+    fn self_signed_cert(key: &PKey<Private>) -> X509 {
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "test-signer")
+            .unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn signer_config(
+        dir: &tempfile::TempDir,
+        key: &PKey<Private>,
+        cert: Option<&X509>,
+    ) -> TokenSignerConfig {
+        let key_path = dir.path().join("token.key");
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let cert_path = cert.map(|cert| {
+            let cert_path = dir.path().join("token.crt");
+            std::fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+            cert_path.to_string_lossy().to_string()
+        });
+
+        TokenSignerConfig {
+            key_path: key_path.to_string_lossy().to_string(),
+            cert_path,
+        }
+    }
+
+    #[test]
+    fn test_load_rsa_signer() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = PKey::from_rsa(Rsa::generate(RSA_KEY_BITS).unwrap()).unwrap();
+        let cert = self_signed_cert(&key);
+        let signer = signer_config(&dir, &key, Some(&cert));
+
+        let jwk = SigningKey::load(&signer, None).unwrap().jwk().unwrap();
+        assert_eq!(jwk["kty"], "RSA");
+        assert_eq!(jwk["alg"], "RS384");
+        assert_eq!(jwk["x5c"][0], STANDARD.encode(cert.to_der().unwrap()));
+
+        // A restarted broker with the same key publishes the same JWK.
+        assert_eq!(jwk, SigningKey::load(&signer, None).unwrap().jwk().unwrap());
+    }
+
+    #[test]
+    fn test_load_ec_signer() {
+        let dir = tempfile::tempdir().unwrap();
+        let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let signer = signer_config(&dir, &key, None);
+
+        let key = SigningKey::load(&signer, None).unwrap();
+        let jwk = key.jwk().unwrap();
+        assert_eq!(jwk["kty"], "EC");
+        assert_eq!(jwk["crv"], "P-384");
+        assert!(jwk.get("x5c").is_none());
+        assert_eq!(key.sign(b"payload").unwrap().len(), 96);
+    }
+
+    #[test]
+    fn test_mismatched_cert() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = PKey::from_rsa(Rsa::generate(RSA_KEY_BITS).unwrap()).unwrap();
+        let other = PKey::from_rsa(Rsa::generate(RSA_KEY_BITS).unwrap()).unwrap();
+        let cert = self_signed_cert(&other);
+        let signer = signer_config(&dir, &key, Some(&cert));
+
+        assert!(SigningKey::load(&signer, None).is_err());
+    }
+
+    #[rstest]
+    #[case(TokenAlgorithm::RS384, "RSA", None)]
+    #[case(TokenAlgorithm::PS384, "RSA", None)]
+    #[case(TokenAlgorithm::ES256, "EC", Some("P-256"))]
+    #[case(TokenAlgorithm::ES384, "EC", Some("P-384"))]
+    #[case(TokenAlgorithm::EdDSA, "OKP", Some("Ed25519"))]
+    fn test_algorithms(#[case] alg: TokenAlgorithm, #[case] kty: &str, #[case] crv: Option<&str>) {
+        let config = AttestationTokenConfig {
+            algorithm: Some(alg),
+            ..Default::default()
+        };
+        let ring = KeyRing::new(config).unwrap();
+
+        let jwk = ring.active().jwk().unwrap();
+        assert_eq!(jwk["alg"], alg.as_str());
+        assert_eq!(jwk["kty"], kty);
+        assert_eq!(jwk["crv"].as_str(), crv);
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let token = issue(ring.active(), now, now + 60);
+        assert!(ring.verify_jwt(&token, "test", None).is_ok());
+    }
+
+    #[test]
+    fn test_incompatible_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = PKey::from_rsa(Rsa::generate(RSA_KEY_BITS).unwrap()).unwrap();
+        let signer = signer_config(&dir, &key, None);
+
+        assert!(SigningKey::load(&signer, Some(TokenAlgorithm::PS384)).is_ok());
+        assert!(SigningKey::load(&signer, Some(TokenAlgorithm::ES256)).is_err());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut ring = KeyRing::new(AttestationTokenConfig::default()).unwrap();
+        let old_kid = ring.active().kid().to_string();
+
+        ring.rotate().unwrap();
+        assert_ne!(ring.active().kid(), old_kid);
+
+        let jwks: Value = serde_json::from_str(&ring.jwks().unwrap()).unwrap();
+        let kids: Vec<&str> = jwks["keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|k| k["kid"].as_str().unwrap())
+            .collect();
+        assert_eq!(kids, vec![ring.active().kid(), old_kid.as_str()]);
+    }
+
+    #[test]
+    fn test_retired_key_expires() {
+        let config = AttestationTokenConfig {
+            retired_key_grace_min: Some(0),
+            ..Default::default()
+        };
+        let mut ring = KeyRing::new(config).unwrap();
+
+        ring.rotate().unwrap();
+        assert_eq!(ring.valid_keys().count(), 1);
+    }
+
+    fn issue(key: &SigningKey, nbf: i64, exp: i64) -> String {
+        key.sign_jwt(&json!({
+            "iss": "test",
+            "nbf": nbf,
+            "exp": exp,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_jwt() {
+        let dir = tempfile::tempdir().unwrap();
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let config = AttestationTokenConfig {
+            signer: Some(signer_config(&dir, &key, None)),
+            ..Default::default()
+        };
+        let ring = KeyRing::new(config).unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let token = issue(ring.active(), now, now + 60);
+        assert_eq!(
+            ring.verify_jwt(&token, "test", None).unwrap()["iss"],
+            "test"
+        );
+        assert!(ring.verify_jwt(&token, "other", None).is_err());
+
+        // Replace the claims with the ones of another token.
+        let other = issue(ring.active(), now, now + 120);
+        let parts: Vec<&str> = token.split('.').collect();
+        let other_parts: Vec<&str> = other.split('.').collect();
+        let tampered = format!("{}.{}.{}", parts[0], other_parts[1], parts[2]);
+        assert!(ring.verify_jwt(&tampered, "test", None).is_err());
+
+        assert!(ring
+            .verify_jwt(&issue(ring.active(), now - 120, now - 60), "test", None)
+            .is_err());
+        assert!(ring
+            .verify_jwt(&issue(ring.active(), now + 60, now + 120), "test", None)
+            .is_err());
+
+        let bound = ring
+            .active()
+            .sign_jwt(&json!({
+                "iss": "test",
+                "aud": "kbs",
+                "nbf": now,
+                "exp": now + 60,
+            }))
+            .unwrap();
+        assert!(ring.verify_jwt(&bound, "test", Some("kbs")).is_ok());
+        assert!(ring.verify_jwt(&bound, "test", Some("other")).is_err());
+        assert!(ring.verify_jwt(&bound, "test", None).is_err());
+        assert!(ring.verify_jwt(&token, "test", Some("kbs")).is_err());
+    }
+
+    #[test]
+    fn test_verify_jwt_after_rotation() {
+        let mut ring = KeyRing::new(AttestationTokenConfig::default()).unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let token = issue(ring.active(), now, now + 60);
+
+        ring.rotate().unwrap();
+        assert!(ring.verify_jwt(&token, "test", None).is_ok());
+
+        let other = KeyRing::new(AttestationTokenConfig::default()).unwrap();
+        assert!(other.verify_jwt(&token, "test", None).is_err());
+    }
+
+    #[test]
+    fn test_rotate_unchanged_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = PKey::from_rsa(Rsa::generate(RSA_KEY_BITS).unwrap()).unwrap();
+        let config = AttestationTokenConfig {
+            signer: Some(signer_config(&dir, &key, None)),
+            ..Default::default()
+        };
+        let mut ring = KeyRing::new(config).unwrap();
+        let kid = ring.active().kid().to_string();
+
+        ring.rotate().unwrap();
+        assert_eq!(ring.active().kid(), kid);
+        assert_eq!(ring.valid_keys().count(), 1);
+    }
+
+    #[test]
+    fn test_scheduled_rotation_of_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = PKey::from_rsa(Rsa::generate(RSA_KEY_BITS).unwrap()).unwrap();
+        let config = AttestationTokenConfig {
+            signer: Some(signer_config(&dir, &key, None)),
+            key_rotation_interval_min: Some(60),
+            ..Default::default()
+        };
+        assert!(KeyRing::new(config).is_err());
+    }
+}
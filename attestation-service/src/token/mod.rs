@@ -8,9 +8,13 @@ use serde::Deserialize;
 use serde_json::Value;
 use strum_macros::EnumString;
 
+mod ear;
+mod jwe;
+mod key;
 mod simple;
 
 const DEFAULT_TOKEN_TIMEOUT: i64 = 5;
+const DEFAULT_ISSUER_NAME: &str = "CoCo-Attestation-Service";
 
 pub trait AttestationTokenBroker {
     /// Issue an signed attestation token with custom claims.
@@ -20,11 +24,22 @@ pub trait AttestationTokenBroker {
     /// Get the public keys and X.509 formatted certificate chain of the attestation token broker.
     /// Returns the certificate chain in [JWKS format](https://www.rfc-editor.org/rfc/rfc7517#appendix-B).
     fn pubkey_jwks(&self) -> Result<String>;
+
+    /// Rotate the signing key. The retired key stays in the JWKS until
+    /// its grace period is over, so that issued tokens can still be verified.
+    fn rotate_key(&self) -> Result<()>;
+
+    /// Verify an attestation token issued by this broker, including the signature,
+    /// the validity period, the issuer and the audience, which must be the
+    /// expected `audience` if the token has one. A token which reports denied
+    /// evidence is rejected. Returns the claims of the token.
+    fn verify(&self, token: &str, audience: Option<&str>) -> Result<Value>;
 }
 
 #[derive(Deserialize, Debug, Clone, EnumString)]
 pub enum AttestationTokenBrokerType {
     Simple,
+    Ear,
 }
 
 impl AttestationTokenBrokerType {
@@ -37,6 +52,32 @@ impl AttestationTokenBrokerType {
                 Ok(Box::new(simple::SimpleAttestationTokenBroker::new(config)?)
                     as Box<dyn AttestationTokenBroker + Send + Sync>)
             }
+            AttestationTokenBrokerType::Ear => {
+                Ok(Box::new(ear::EarAttestationTokenBroker::new(config)?)
+                    as Box<dyn AttestationTokenBroker + Send + Sync>)
+            }
+        }
+    }
+}
+
+/// The JWS algorithm to sign the Attestation Result Token.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+pub enum TokenAlgorithm {
+    RS384,
+    PS384,
+    ES256,
+    ES384,
+    EdDSA,
+}
+
+impl TokenAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenAlgorithm::RS384 => "RS384",
+            TokenAlgorithm::PS384 => "PS384",
+            TokenAlgorithm::ES256 => "ES256",
+            TokenAlgorithm::ES384 => "ES384",
+            TokenAlgorithm::EdDSA => "EdDSA",
         }
     }
 }
@@ -46,7 +87,58 @@ pub struct AttestationTokenConfig {
     /// The Attestation Result Token duration time(in minute)
     pub duration_min: i64,
 
+    /// The `iss` claim of the Attestation Result Token.
+    /// Defaults to `CoCo-Attestation-Service`.
     pub issuer_name: Option<String>,
+
+    /// Configuration of the key used to sign the Attestation Result Token.
+    /// If not set, a new key will be generated each time the broker starts.
+    #[serde(default)]
+    pub signer: Option<TokenSignerConfig>,
+
+    /// The algorithm to sign the token. If not set, it is derived from the
+    /// type of the configured signing key, or `RS384` for a generated key.
+    /// The configured signing key must match the algorithm: an RSA key for
+    /// `RS384`/`PS384`, a P-256 or P-384 key for `ES256`/`ES384`, and an
+    /// Ed25519 key for `EdDSA`.
+    #[serde(default)]
+    pub algorithm: Option<TokenAlgorithm>,
+
+    /// Encrypt the signed token as a JWE (`RSA-OAEP` + `A256GCM`) to the
+    /// `tee-pubkey` of the attestation, s.t. only the attested TEE can read it.
+    #[serde(default)]
+    pub encrypt_token: bool,
+
+    /// Rotate the generated signing key automatically every given minutes.
+    /// If not set, the key is only rotated on demand. It cannot be set with a
+    /// `signer`, whose key is rotated by replacing the key file, which is
+    /// picked up on a rotation on demand.
+    #[serde(default)]
+    pub key_rotation_interval_min: Option<i64>,
+
+    /// How long(in minute) a retired signing key is still published in the JWKS.
+    /// Defaults to `duration_min`, s.t. all the tokens signed by it have expired.
+    #[serde(default)]
+    pub retired_key_grace_min: Option<i64>,
+}
+
+impl AttestationTokenConfig {
+    /// Get the issuer name of the tokens.
+    pub fn issuer(&self) -> &str {
+        self.issuer_name.as_deref().unwrap_or(DEFAULT_ISSUER_NAME)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TokenSignerConfig {
+    /// Path of the PEM encoded private key (RSA or EC) to sign the token.
+    pub key_path: String,
+
+    /// Path of the PEM encoded X.509 certificate chain of the signing key.
+    /// The first certificate must be the one of the signing key.
+    /// It will be published as `x5c` of the token broker's JWKS.
+    #[serde(default)]
+    pub cert_path: Option<String>,
 }
 
 impl Default for AttestationTokenConfig {
@@ -54,6 +146,11 @@ impl Default for AttestationTokenConfig {
         Self {
             duration_min: DEFAULT_TOKEN_TIMEOUT,
             issuer_name: None,
+            signer: None,
+            algorithm: None,
+            encrypt_token: false,
+            key_rotation_interval_min: None,
+            retired_key_grace_min: None,
         }
     }
 }
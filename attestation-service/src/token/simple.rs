@@ -3,62 +3,38 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::*;
-use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-use base64::Engine;
-use rsa::pkcs1v15::SigningKey;
-use rsa::sha2::Sha384;
-use rsa::signature::{RandomizedSigner, SignatureEncoding};
-use rsa::traits::PublicKeyParts;
-use rsa::RsaPrivateKey;
 use serde_json::{json, Value};
 
+use crate::token::jwe;
+use crate::token::key::SharedKeyRing;
 use crate::token::{AttestationTokenBroker, AttestationTokenConfig};
 
-const ISSUER_NAME: &str = "CoCo-Attestation-Service";
-const RSA_KEY_BITS: usize = 2048;
-const SIMPLE_TOKEN_ALG: &str = "RS384";
-
 pub struct SimpleAttestationTokenBroker {
-    private_key: RsaPrivateKey,
+    keys: SharedKeyRing,
     config: AttestationTokenConfig,
 }
 
 impl SimpleAttestationTokenBroker {
     pub fn new(config: AttestationTokenConfig) -> Result<Self> {
-        let mut rng = rand::thread_rng();
-        let private_key = RsaPrivateKey::new(&mut rng, RSA_KEY_BITS)?;
-
-        Ok(Self {
-            private_key,
-            config,
-        })
-    }
-}
+        let keys = SharedKeyRing::new(config.clone())?;
 
-impl SimpleAttestationTokenBroker {
-    fn rs384_sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
-        let mut rng = rand::thread_rng();
-        let signing_key = SigningKey::<Sha384>::new(self.private_key.clone());
-        let signature = signing_key.sign_with_rng(&mut rng, payload);
-        Ok(signature.to_bytes().to_vec())
+        Ok(Self { keys, config })
     }
 }
 
 impl AttestationTokenBroker for SimpleAttestationTokenBroker {
     fn issue(&self, custom_claims: Value) -> Result<String> {
-        let header_value = json!({
-            "typ": "JWT",
-            "alg": SIMPLE_TOKEN_ALG,
-        });
-        let header_string = serde_json::to_string(&header_value)?;
-        let header_b64 = URL_SAFE_NO_PAD.encode(header_string.as_bytes());
+        let keys = self.keys.read()?;
+        let key = keys.active();
 
         let now = time::OffsetDateTime::now_utc();
         let exp = now + time::Duration::minutes(self.config.duration_min);
 
         let mut claims = json!({
-            "iss": ISSUER_NAME,
-            "jwk": serde_json::from_str::<Value>(&self.pubkey_jwks()?)?["keys"][0].clone(),
+            "iss": self.config.issuer(),
+            "jwk": key.jwk()?,
+            "iat": now.unix_timestamp(),
+            "jti": uuid::Uuid::new_v4().to_string(),
             "nbf": now.unix_timestamp(),
             "exp": exp.unix_timestamp(),
         })
@@ -73,34 +49,133 @@ impl AttestationTokenBroker for SimpleAttestationTokenBroker {
                 .to_owned(),
         );
 
-        let claims_value = Value::Object(claims);
-        let claims_string = serde_json::to_string(&claims_value)?;
-        let claims_b64 = URL_SAFE_NO_PAD.encode(claims_string.as_bytes());
-
-        let signature_payload = format!("{header_b64}.{claims_b64}");
-        let signature = self.rs384_sign(signature_payload.as_bytes())?;
-        let signature_b64 = URL_SAFE_NO_PAD.encode(signature);
-
-        let token = format!("{signature_payload}.{signature_b64}");
+        let claims = Value::Object(claims);
+        let token = key.sign_jwt(&claims)?;
+        if self.config.encrypt_token {
+            return jwe::encrypt(&token, &claims["tee-pubkey"]);
+        }
 
         Ok(token)
     }
 
     fn pubkey_jwks(&self) -> Result<String> {
-        let pubkey = self.private_key.to_public_key();
-        let n = pubkey.n().to_bytes_be();
-        let e = pubkey.e().to_bytes_be();
-
-        let jwk = json!({
-            "kty": "RSA",
-            "alg": SIMPLE_TOKEN_ALG,
-            "n": URL_SAFE_NO_PAD.encode(n),
-            "e": URL_SAFE_NO_PAD.encode(e),
-        });
-        let jwks = json!({
-            "keys": vec![jwk],
-        });
-
-        Ok(serde_json::to_string(&jwks)?)
+        self.keys.jwks()
+    }
+
+    fn rotate_key(&self) -> Result<()> {
+        self.keys.rotate()
+    }
+
+    fn verify(&self, token: &str, audience: Option<&str>) -> Result<Value> {
+        let claims = self
+            .keys
+            .verify_jwt(token, self.config.issuer(), audience)?;
+        if claims["allow"].as_bool() != Some(true) {
+            bail!("Token reports denied evidence");
+        }
+
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    fn header(token: &str) -> Value {
+        let header = URL_SAFE_NO_PAD
+            .decode(token.split('.').next().unwrap())
+            .unwrap();
+        serde_json::from_slice(&header).unwrap()
+    }
+
+    #[test]
+    fn test_issue_after_rotation() {
+        let broker = SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+        let old_kid = header(&broker.issue(json!({})).unwrap())["kid"].clone();
+
+        broker.rotate_key().unwrap();
+        let new_kid = header(&broker.issue(json!({})).unwrap())["kid"].clone();
+        assert_ne!(old_kid, new_kid);
+
+        let jwks: Value = serde_json::from_str(&broker.pubkey_jwks().unwrap()).unwrap();
+        assert_eq!(jwks["keys"][0]["kid"], new_kid);
+        assert_eq!(jwks["keys"][1]["kid"], old_kid);
+    }
+
+    #[test]
+    fn test_scheduled_rotation() {
+        let config = AttestationTokenConfig {
+            key_rotation_interval_min: Some(0),
+            ..Default::default()
+        };
+        let broker = SimpleAttestationTokenBroker::new(config).unwrap();
+
+        let first = header(&broker.issue(json!({})).unwrap())["kid"].clone();
+        let second = header(&broker.issue(json!({})).unwrap())["kid"].clone();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_standard_claims() {
+        let config = AttestationTokenConfig {
+            issuer_name: Some("test-issuer".to_string()),
+            ..Default::default()
+        };
+        let broker = SimpleAttestationTokenBroker::new(config).unwrap();
+
+        let token = broker
+            .issue(json!({
+                "aud": "kbs",
+                "sub": "workload",
+                "allow": true,
+            }))
+            .unwrap();
+        let claims = broker.verify(&token, Some("kbs")).unwrap();
+        assert_eq!(claims["iss"], "test-issuer");
+        assert_eq!(claims["aud"], "kbs");
+        assert_eq!(claims["sub"], "workload");
+        assert!(claims["iat"].is_i64());
+
+        let other = broker
+            .verify(&broker.issue(json!({"allow": true})).unwrap(), None)
+            .unwrap();
+        assert_ne!(claims["jti"], other["jti"]);
+    }
+
+    #[test]
+    fn test_verify_denied() {
+        let broker = SimpleAttestationTokenBroker::new(AttestationTokenConfig::default()).unwrap();
+
+        let token = broker.issue(json!({"allow": false})).unwrap();
+        assert!(broker.verify(&token, None).is_err());
+        let token = broker.issue(json!({})).unwrap();
+        assert!(broker.verify(&token, None).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_token() {
+        let config = AttestationTokenConfig {
+            encrypt_token: true,
+            ..Default::default()
+        };
+        let broker = SimpleAttestationTokenBroker::new(config).unwrap();
+        let rsa = openssl::rsa::Rsa::generate(2048).unwrap();
+
+        let token = broker
+            .issue(json!({
+                "tee-pubkey": {
+                    "kty": "RSA",
+                    "alg": "RSA1_5",
+                    "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+                    "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+                },
+            }))
+            .unwrap();
+        assert_eq!(token.split('.').count(), 5);
+
+        assert!(broker.issue(json!({})).is_err());
     }
 }
@@ -72,12 +72,56 @@ fn flatten_helper(parent: &mut Map<String, Value>, child: &serde_json::Value, pr
     }
 }
 
+/// The token claims which are set by the AS itself, and cannot be set by the
+/// `claims` of the policies.
+const RESERVED_CLAIMS: [&str; 13] = [
+    "iss",
+    "jwk",
+    "iat",
+    "jti",
+    "nbf",
+    "exp",
+    "aud",
+    "sub",
+    "tee",
+    "tee-pubkey",
+    "tcb-status",
+    "allow",
+    "evaluation-reports",
+];
+
+/// Merge the `claims` of a policy decision into the top-level token claims.
+/// Reserved claims are ignored, and a claim which is already set, e.g. by a
+/// previous policy, is kept.
+pub fn merge_policy_claims(
+    token_claims: &mut Map<String, Value>,
+    policy_id: &str,
+    policy_claims: &Map<String, Value>,
+) {
+    for (name, value) in policy_claims {
+        if RESERVED_CLAIMS.contains(&name.as_str()) {
+            warn!("Policy {policy_id} cannot set the reserved claim {name}");
+            continue;
+        }
+        match token_claims.get(name) {
+            Some(current) if current != value => {
+                warn!("Policy {policy_id} cannot override the claim {name}: {current}")
+            }
+            Some(_) => {}
+            None => {
+                token_claims.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use assert_json_diff::assert_json_eq;
     use serde_json::json;
 
-    use super::flatten_claims;
+    use super::{flatten_claims, merge_policy_claims};
+    use serde_json::Value;
 
     #[test]
     fn flatten() {
@@ -140,4 +184,35 @@ mod tests {
         });
         assert_json_eq!(expected, flatten);
     }
+
+    #[test]
+    fn merge_claims() {
+        let mut token_claims = json!({
+            "tee": "tdx",
+            "allow": true,
+        })
+        .as_object()
+        .unwrap()
+        .clone();
+
+        let first = json!({
+            "tier": "gold",
+            "allow": false,
+            "exp": 0,
+        });
+        merge_policy_claims(&mut token_claims, "first", first.as_object().unwrap());
+        let second = json!({
+            "tier": "silver",
+            "workload": "db",
+        });
+        merge_policy_claims(&mut token_claims, "second", second.as_object().unwrap());
+
+        let expected = json!({
+            "tee": "tdx",
+            "allow": true,
+            "tier": "gold",
+            "workload": "db",
+        });
+        assert_json_eq!(Value::Object(token_claims), expected);
+    }
 }
@@ -0,0 +1,63 @@
+//! The claims of an SEV-SNP attestation report, shared by the SEV-SNP
+//! verifiers s.t. their claim names are the same. Fields of raw bytes are
+//! base64 encoded, and the other fields are decimal strings.
+
+use as_types::TeeEvidenceParsedClaim;
+use base64::Engine;
+use serde_json::{json, Map, Value};
+use sev::firmware::guest::AttestationReport;
+use sev::firmware::host::TcbVersion;
+
+/// Insert the components of the TCB as `<prefix>_bootloader`, `<prefix>_tee`,
+/// `<prefix>_snp` and `<prefix>_microcode`.
+fn insert_tcb(claims: &mut Map<String, Value>, prefix: &str, tcb: &TcbVersion) {
+    let components = [
+        ("bootloader", tcb.bootloader),
+        ("tee", tcb.tee),
+        ("snp", tcb.snp),
+        ("microcode", tcb.microcode),
+    ];
+    for (name, value) in components {
+        claims.insert(format!("{prefix}_{name}"), json!(value.to_string()));
+    }
+}
+
+pub fn parse_report(report: &AttestationReport) -> TeeEvidenceParsedClaim {
+    let engine = base64::engine::general_purpose::STANDARD;
+    let mut claims_map = json!({
+        // policy fields
+        "policy_abi_major": format!("{}", report.policy.abi_major()),
+        "policy_abi_minor": format!("{}", report.policy.abi_minor()),
+        "policy_smt_allowed": format!("{}", report.policy.smt_allowed()),
+        "policy_migrate_ma": format!("{}", report.policy.migrate_ma_allowed()),
+        "policy_debug_allowed": format!("{}", report.policy.debug_allowed()),
+        "policy_single_socket": format!("{}", report.policy.single_socket_required()),
+
+        // platform info
+        "platform_tsme_enabled": format!("{}", report.plat_info.tsme_enabled()),
+        "platform_smt_enabled": format!("{}", report.plat_info.smt_enabled()),
+
+        // guest info
+        "guest_svn": format!("{}", report.guest_svn),
+        "family_id": engine.encode(report.family_id),
+        "image_id": engine.encode(report.image_id),
+        "host_data": engine.encode(report.host_data),
+        "id_key_digest": engine.encode(report.id_key_digest),
+        "author_key_digest": engine.encode(report.author_key_digest),
+        "report_id": engine.encode(report.report_id),
+        "chip_id": engine.encode(report.chip_id),
+
+        // measurement
+        "measurement": engine.encode(report.measurement),
+    });
+
+    // versioning info
+    if let Some(claims) = claims_map.as_object_mut() {
+        insert_tcb(claims, "reported_tcb", &report.reported_tcb);
+        insert_tcb(claims, "current_tcb", &report.current_tcb);
+        insert_tcb(claims, "committed_tcb", &report.committed_tcb);
+        insert_tcb(claims, "launch_tcb", &report.launch_tcb);
+    }
+
+    claims_map as TeeEvidenceParsedClaim
+}
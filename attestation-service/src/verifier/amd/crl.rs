@@ -0,0 +1,204 @@
+//! Revocation checking of the AMD certificates with the CRLs of the AMD KDS.
+//!
+//! Each product generation has a CRL signed by its ARK, which lists the
+//! revoked ASKs and ASVKs. Only these are checked: the VCEKs and VLEKs are
+//! issued by the ASK or ASVK, so they are never listed by the CRL of the ARK.
+//! The CRL is taken from the config, or else from the cache of the
+//! certificate provider, which fetches it again from the KDS once the cached
+//! CRL is outdated.
+
+use anyhow::{bail, Context, Result};
+use openssl::asn1::Asn1Time;
+use openssl::x509::{CrlStatus, X509Crl, X509CrlRef};
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+
+use super::provider::CertificateProvider;
+use super::{names_eq, AmdChain};
+use crate::verifier::SnpConfig;
+
+/// Load a PEM or DER encoded CRL.
+pub fn load_crl(data: &[u8]) -> Result<X509Crl> {
+    X509Crl::from_pem(data)
+        .or_else(|_| X509Crl::from_der(data))
+        .context("Malformed CRL")
+}
+
+/// Whether the next update of the CRL is still to come. A CRL without the
+/// next update is never fresh.
+pub fn is_fresh(crl: &X509CrlRef) -> Result<bool> {
+    let Some(next_update) = crl.next_update() else {
+        return Ok(false);
+    };
+    let now = Asn1Time::days_from_now(0)?;
+
+    Ok(next_update.compare(&now)? == Ordering::Greater)
+}
+
+/// The CRL which the certificates are checked against.
+#[derive(Debug)]
+pub struct RevocationStatus {
+    pub this_update: String,
+    pub next_update: Option<String>,
+    pub fresh: bool,
+}
+
+/// The claims of the revocation check, with `crl_checked` false if no CRL
+/// is available.
+pub fn revocation_claims(status: Option<&RevocationStatus>) -> Map<String, Value> {
+    let claims = match status {
+        Some(status) => json!({
+            "crl_checked": "true",
+            "crl_this_update": status.this_update,
+            "crl_next_update": status.next_update.clone().unwrap_or_default(),
+            "crl_fresh": format!("{}", status.fresh),
+        }),
+        None => json!({
+            "crl_checked": "false",
+        }),
+    };
+
+    match claims {
+        Value::Object(claims) => claims,
+        _ => Map::new(),
+    }
+}
+
+/// Verify the CRL with the ARK of the chain, and check that the ASK or ASVK
+/// is not revoked by it.
+pub fn check_revocation(crl: &X509CrlRef, chain: &AmdChain) -> Result<RevocationStatus> {
+    if !names_eq(crl.issuer_name(), chain.ark.subject_name()) {
+        bail!("CRL is not issued by the ARK");
+    }
+
+    let ark_key = chain.ark.public_key()?;
+    if !crl.verify(&ark_key).context("Invalid CRL Signature")? {
+        bail!("Invalid CRL Signature");
+    }
+
+    if let CrlStatus::Revoked(_) = crl.get_by_cert(&chain.ask) {
+        bail!("ASK has been revoked");
+    }
+
+    Ok(RevocationStatus {
+        this_update: crl.last_update().to_string(),
+        next_update: crl.next_update().map(|time| time.to_string()),
+        fresh: is_fresh(crl)?,
+    })
+}
+
+/// Checks the revocation of the AMD certificates for the SNP verifier.
+pub struct RevocationChecker {
+    crls: Vec<X509Crl>,
+    require_crl: bool,
+}
+
+impl RevocationChecker {
+    pub fn new(config: &SnpConfig) -> Result<Self> {
+        let mut crls = vec![];
+        for path in &config.crls {
+            let data = std::fs::read(path)
+                .with_context(|| format!("Read AMD CRL {} failed", path.display()))?;
+            crls.push(load_crl(&data).with_context(|| format!("Load {}", path.display()))?);
+        }
+
+        Ok(Self {
+            crls,
+            require_crl: config.require_crl,
+        })
+    }
+
+    /// Check the chain against the configured CRL of the ARK, or else the CRL
+    /// of the product from the provider. Without a CRL, e.g. if the KDS is
+    /// unreachable and no CRL is cached, the check is skipped and `None`
+    /// returned, unless a CRL is required.
+    pub async fn check(
+        &self,
+        chain: &AmdChain,
+        provider: &CertificateProvider,
+    ) -> Result<Option<RevocationStatus>> {
+        if let Some(crl) = self
+            .crls
+            .iter()
+            .find(|crl| names_eq(crl.issuer_name(), chain.ark.subject_name()))
+        {
+            return check_revocation(crl, chain).map(Some);
+        }
+
+        let crl = match chain.product_name() {
+            Some(product) => match provider.get_crl(&product).await {
+                Ok(crl) => crl,
+                Err(e) if self.require_crl => return Err(e),
+                Err(e) => {
+                    warn!("Skip the revocation check, no CRL of {product}: {e}");
+                    None
+                }
+            },
+            None => None,
+        };
+        match crl {
+            Some(crl) => check_revocation(&crl, chain).map(Some),
+            None if self.require_crl => bail!("No CRL found for the AMD chain"),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::amd::load_milan_cert_chain;
+    use crate::verifier::amd::provider::KdsFetcher;
+
+    fn test_chain() -> AmdChain {
+        AmdChain::from_pem(include_bytes!("test-ask-ark.pem")).unwrap()
+    }
+
+    #[test]
+    fn test_check_revocation() {
+        let chain = test_chain();
+
+        let crl = load_crl(include_bytes!("test-crl.pem")).unwrap();
+        let status = check_revocation(&crl, &chain).unwrap();
+        assert!(status.fresh);
+        assert_eq!(revocation_claims(Some(&status))["crl_fresh"], "true");
+
+        let crl = load_crl(include_bytes!("test-crl-stale.pem")).unwrap();
+        let status = check_revocation(&crl, &chain).unwrap();
+        assert!(!status.fresh);
+        assert_eq!(status.next_update.unwrap(), "Feb  1 00:00:00 2020 GMT");
+
+        // the ASK is revoked
+        let crl = load_crl(include_bytes!("test-crl-revoked.pem")).unwrap();
+        assert!(check_revocation(&crl, &chain).is_err());
+    }
+
+    #[test]
+    fn test_check_revocation_other_ark() {
+        let milan = load_milan_cert_chain().unwrap();
+        let crl = load_crl(include_bytes!("test-crl.pem")).unwrap();
+        assert!(check_revocation(&crl, &milan).is_err());
+
+        assert_eq!(revocation_claims(None)["crl_checked"], "false");
+    }
+
+    #[tokio::test]
+    async fn test_check_unreachable_kds() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = CertificateProvider::with_fetcher(
+            dir.path().to_path_buf(),
+            Some(Box::new(KdsFetcher::new("http://127.0.0.1:1"))),
+        );
+        let mut config = SnpConfig::default();
+        let checker = RevocationChecker::new(&config).unwrap();
+        assert!(checker
+            .check(&test_chain(), &provider)
+            .await
+            .unwrap()
+            .is_none());
+
+        config.require_crl = true;
+        let checker = RevocationChecker::new(&config).unwrap();
+        assert!(checker.check(&test_chain(), &provider).await.is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! The AMD certificate chains shared by the SEV-SNP verifiers.
+//!
+//! Each product generation has its own ARK and ASK. The chain of a VCEK is
+//! chosen by the issuer of the VCEK, which is the ASK of its generation, e.g.
+//! `SEV-Milan`, or the ASVK of its generation for a VLEK, e.g. `SEV-VLEK-Milan`.
+//! Bergamo and Siena are certified by the Genoa ASK.
+//!
+//! The ASVKs are not bundled. They are given by the host along with the VLEK,
+//! or resolved by the certificate provider, and are trusted if signed by the
+//! ARK of a trusted chain.
+
+use anyhow::{anyhow, bail, Context, Result};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::x509::{X509NameRef, X509};
+use serde::{Deserialize, Serialize};
+
+use super::SnpConfig;
+
+pub mod claims;
+#[cfg(feature = "snp-verifier")]
+pub mod crl;
+#[cfg(feature = "snp-verifier")]
+pub mod provider;
+
+/// The AMD product generations supporting SEV-SNP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessorGeneration {
+    Milan,
+    Genoa,
+    Bergamo,
+    Siena,
+}
+
+impl ProcessorGeneration {
+    /// The name of the generation in the AMD KDS, and in the names of its ASK
+    /// and ASVK.
+    pub fn kds_name(&self) -> &'static str {
+        match self {
+            ProcessorGeneration::Milan => "Milan",
+            ProcessorGeneration::Genoa
+            | ProcessorGeneration::Bergamo
+            | ProcessorGeneration::Siena => "Genoa",
+        }
+    }
+}
+
+/// A trusted ASK, or ASVK, and the ARK which signs it.
+#[derive(Clone)]
+pub struct AmdChain {
+    pub ask: X509,
+    pub ark: X509,
+}
+
+impl AmdChain {
+    /// Load the chain from PEM, with the ASK followed by the ARK.
+    fn from_pem(pem: &[u8]) -> Result<Self> {
+        let certs = X509::stack_from_pem(pem)?;
+        if certs.len() != 2 {
+            bail!("Malformed ASK/ARK");
+        }
+
+        let chain = Self {
+            ask: certs[0].clone(),
+            ark: certs[1].clone(),
+        };
+        chain.verify()?;
+
+        Ok(chain)
+    }
+
+    fn verify(&self) -> Result<()> {
+        let ark_key = self.ark.public_key()? as PKey<Public>;
+
+        // ARK -> ARK
+        if !self.ark.verify(&ark_key).context("Invalid ARK Signature")? {
+            bail!("Invalid ARK Signature");
+        }
+
+        // ARK -> ASK
+        if !self.ask.verify(&ark_key).context("Invalid ASK Signature")? {
+            bail!("Invalid ASK Signature");
+        }
+
+        Ok(())
+    }
+
+    /// The product generation named by the ARK, e.g. `Milan` for `ARK-Milan`.
+    pub fn product_name(&self) -> Option<String> {
+        common_name(self.ark.subject_name())?
+            .strip_prefix("ARK-")
+            .map(|name| name.to_string())
+    }
+
+    /// Check whether the VCEK or VLEK is signed by the ASK or ASVK.
+    fn signs(&self, vcek: &X509) -> bool {
+        let Ok(ask_key) = self.ask.public_key() else {
+            return false;
+        };
+        names_eq(vcek.issuer_name(), self.ask.subject_name())
+            && vcek.verify(&ask_key).unwrap_or(false)
+    }
+}
+
+fn names_eq(a: &X509NameRef, b: &X509NameRef) -> bool {
+    matches!((a.to_der(), b.to_der()), (Ok(a), Ok(b)) if a == b)
+}
+
+pub fn common_name(name: &X509NameRef) -> Option<String> {
+    name.entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|cn| cn.to_string())
+}
+
+/// The product generation of the ASVK which issues the VLEK, e.g. `Milan` for
+/// `SEV-VLEK-Milan`.
+pub fn vlek_product(vlek: &X509) -> Option<String> {
+    common_name(vlek.issuer_name())?
+        .strip_prefix("SEV-VLEK-")
+        .map(|name| name.to_string())
+}
+
+pub fn load_milan_cert_chain() -> Result<AmdChain> {
+    AmdChain::from_pem(include_bytes!("milan_ask_ark.pem")).context("Malformed Milan ASK/ARK")
+}
+
+pub fn load_genoa_cert_chain() -> Result<AmdChain> {
+    // The Genoa chain is bundled by the sev crate.
+    use sev::certs::snp::builtin::genoa;
+
+    let pem = [genoa::ASK, genoa::ARK].concat();
+    AmdChain::from_pem(&pem).context("Malformed Genoa ASK/ARK")
+}
+
+/// The chains trusted by the SEV-SNP verifiers: the bundled chains of the
+/// known product generations, and the extra chains given in the config.
+pub struct TrustedChains(Vec<AmdChain>);
+
+impl TrustedChains {
+    pub fn new(config: &SnpConfig) -> Result<Self> {
+        let mut chains = vec![load_milan_cert_chain()?, load_genoa_cert_chain()?];
+        for path in &config.trusted_chains {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Read trusted AMD chain {} failed", path.display()))?;
+            let chain = AmdChain::from_pem(&pem)
+                .with_context(|| format!("Malformed trusted AMD chain {}", path.display()))?;
+            chains.push(chain);
+        }
+
+        Ok(Self(chains))
+    }
+
+    /// Find the trusted chain which signs the VCEK or VLEK. If the product
+    /// generation is given by the evidence, the key must be issued by the ASK
+    /// or ASVK of the generation. A VLEK may also be issued by the given ASVK,
+    /// if the ASVK is signed by a trusted ARK.
+    pub fn chain_of(
+        &self,
+        vcek: &X509,
+        product: Option<ProcessorGeneration>,
+        asvk: Option<&X509>,
+    ) -> Result<AmdChain> {
+        let issuer = common_name(vcek.issuer_name()).unwrap_or_default();
+        if let Some(product) = product {
+            if !issuer.ends_with(&format!("-{}", product.kds_name())) {
+                bail!("VCEK issuer {issuer} does not match product {product:?}");
+            }
+        }
+
+        if let Some(chain) = self.0.iter().find(|chain| chain.signs(vcek)) {
+            return Ok(chain.clone());
+        }
+
+        asvk.and_then(|asvk| {
+            self.0
+                .iter()
+                .filter(|chain| names_eq(asvk.issuer_name(), chain.ark.subject_name()))
+                .map(|chain| AmdChain {
+                    ask: asvk.clone(),
+                    ark: chain.ark.clone(),
+                })
+                .find(|chain| chain.verify().is_ok() && chain.signs(vcek))
+        })
+        .ok_or_else(|| anyhow!("VCEK issued by {issuer} is not signed by a trusted AMD chain"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_bundled_certificates() {
+        let milan = load_milan_cert_chain().unwrap();
+        assert_eq!(common_name(milan.ark.subject_name()).unwrap(), "ARK-Milan");
+        assert_eq!(common_name(milan.ask.subject_name()).unwrap(), "SEV-Milan");
+        assert_eq!(milan.product_name().unwrap(), "Milan");
+
+        let genoa = load_genoa_cert_chain().unwrap();
+        assert_eq!(common_name(genoa.ark.subject_name()).unwrap(), "ARK-Genoa");
+        assert_eq!(common_name(genoa.ask.subject_name()).unwrap(), "SEV-Genoa");
+    }
+
+    #[test]
+    fn check_extra_trusted_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chain.pem");
+        std::fs::write(&path, include_bytes!("milan_ask_ark.pem")).unwrap();
+        let config = SnpConfig {
+            trusted_chains: vec![path],
+            ..Default::default()
+        };
+        assert_eq!(TrustedChains::new(&config).unwrap().0.len(), 3);
+
+        // The ARK must sign the ASK.
+        let milan = load_milan_cert_chain().unwrap();
+        let pem = [milan.ark.to_pem().unwrap(), milan.ask.to_pem().unwrap()].concat();
+        std::fs::write(&config.trusted_chains[0], pem).unwrap();
+        assert!(TrustedChains::new(&config).is_err());
+    }
+
+    #[test]
+    fn check_vlek_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chain.pem");
+        std::fs::write(&path, include_bytes!("test-lab-ask-ark.pem")).unwrap();
+        let config = SnpConfig {
+            trusted_chains: vec![path],
+            ..Default::default()
+        };
+        let chains = TrustedChains::new(&config).unwrap();
+        let vlek = X509::from_der(include_bytes!("../snp/test-vlek.der")).unwrap();
+        let asvk = X509::from_pem(include_bytes!("test-lab-asvk.pem")).unwrap();
+        assert_eq!(vlek_product(&vlek).unwrap(), "Lab");
+
+        // The ASVK is signed by the trusted ARK.
+        let chain = chains.chain_of(&vlek, None, Some(&asvk)).unwrap();
+        assert_eq!(
+            common_name(chain.ask.subject_name()).unwrap(),
+            "SEV-VLEK-Lab"
+        );
+        assert_eq!(chain.product_name().unwrap(), "Lab");
+        assert!(chains.chain_of(&vlek, None, None).is_err());
+        assert!(chains
+            .chain_of(&vlek, Some(ProcessorGeneration::Milan), Some(&asvk))
+            .is_err());
+
+        // The ASVK is not signed by a trusted ARK.
+        let chains = TrustedChains::new(&SnpConfig::default()).unwrap();
+        assert!(chains.chain_of(&vlek, None, Some(&asvk)).is_err());
+
+        // An ASVK may also be trusted itself, like an ASK.
+        let ark = X509::stack_from_pem(include_bytes!("test-lab-ask-ark.pem")).unwrap()[1].clone();
+        let pem = [asvk.to_pem().unwrap(), ark.to_pem().unwrap()].concat();
+        std::fs::write(&config.trusted_chains[0], pem).unwrap();
+        let chains = TrustedChains::new(&config).unwrap();
+        assert!(chains.chain_of(&vlek, None, None).is_ok());
+    }
+
+    #[test]
+    fn check_product_names() {
+        assert_eq!(ProcessorGeneration::Siena.kds_name(), "Genoa");
+        assert_eq!(ProcessorGeneration::Milan.kds_name(), "Milan");
+    }
+}
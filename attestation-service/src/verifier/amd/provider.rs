@@ -0,0 +1,490 @@
+//! Resolution of the VCEK or VLEK which signs an SEV-SNP attestation report,
+//! if the guest does not give it in the evidence.
+//!
+//! The certificates are looked up in a local cache directory, s.t. the
+//! verifier works offline. A missing VCEK can be fetched from the AMD Key
+//! Distribution Service (KDS) and is then put into the cache. VLEKs are not
+//! published by the KDS, so they must be put into the cache by the operator,
+//! as `vlek/<provider>/<product>-<tcb>.der`, e.g.
+//! `vlek/example-cloud/Milan-03000873.der`, with a directory for
+//! each cloud provider since the VLEKs of the providers differ. The ASVKs
+//! which issue the VLEKs are cached and fetched like the VCEKs.
+//!
+//! The CRLs of the product generations are cached in the same way, and are
+//! fetched again once outdated.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use openssl::x509::{X509Crl, X509};
+use sev::firmware::host::TcbVersion;
+use std::path::{Path, PathBuf};
+
+use super::crl::{is_fresh, load_crl};
+use super::ProcessorGeneration;
+use crate::verifier::SnpConfig;
+
+/// The default cache directory under the work dir of the AS.
+const DEFAULT_CACHE_DIR: &str = "snp/certs";
+
+/// The key which signs the attestation report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SigningKey {
+    Vcek,
+    Vlek,
+}
+
+/// Identifies the endorsement key of an attestation report.
+#[derive(Clone, Debug)]
+pub struct KeyId {
+    pub signing_key: SigningKey,
+    pub chip_id: [u8; 64],
+    pub tcb: TcbVersion,
+    pub product: Option<ProcessorGeneration>,
+}
+
+impl KeyId {
+    fn tcb_hex(&self) -> String {
+        format!(
+            "{:02x}{:02x}{:02x}{:02x}",
+            self.tcb.bootloader, self.tcb.tee, self.tcb.snp, self.tcb.microcode
+        )
+    }
+
+    /// The KDS names of the product generations the key may belong to. Without
+    /// a product in the evidence, these are all the known generations.
+    fn products(&self) -> Vec<&'static str> {
+        match self.product {
+            Some(product) => vec![product.kds_name()],
+            None => vec![
+                ProcessorGeneration::Milan.kds_name(),
+                ProcessorGeneration::Genoa.kds_name(),
+            ],
+        }
+    }
+}
+
+/// A backend to get the certificates missing in the cache.
+#[async_trait]
+pub trait CertificateFetcher {
+    /// Get the DER encoded certificate, or `None` if it is not known.
+    async fn fetch(&self, key: &KeyId) -> Result<Option<Vec<u8>>>;
+
+    /// Get the DER encoded CRL of the product, or `None` if it is not known.
+    async fn fetch_crl(&self, product: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Get the DER encoded ASVK of the product, or `None` if it is not known.
+    async fn fetch_asvk(&self, product: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// Fetches VCEKs, ASVKs and CRLs from the AMD KDS, or a service with the same
+/// API.
+pub struct KdsFetcher {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl KdsFetcher {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl CertificateFetcher for KdsFetcher {
+    async fn fetch(&self, key: &KeyId) -> Result<Option<Vec<u8>>> {
+        if key.signing_key != SigningKey::Vcek {
+            return Ok(None);
+        }
+
+        // Without a product in the evidence, try the products one by one.
+        for product in key.products() {
+            let url = format!(
+                "{}/vcek/v1/{product}/{}?blSPL={}&teeSPL={}&snpSPL={}&ucodeSPL={}",
+                self.base_url,
+                hex::encode(key.chip_id),
+                key.tcb.bootloader,
+                key.tcb.tee,
+                key.tcb.snp,
+                key.tcb.microcode
+            );
+            let res = self
+                .client
+                .get(&url)
+                .send()
+                .await
+                .map_err(|e| anyhow!("Fetch VCEK from KDS failed: {e}"))?;
+            match res.status() {
+                reqwest::StatusCode::OK => return Ok(Some(res.bytes().await?.to_vec())),
+                reqwest::StatusCode::NOT_FOUND => continue,
+                status => bail!("Fetch VCEK from KDS failed: {status}"),
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn fetch_crl(&self, product: &str) -> Result<Option<Vec<u8>>> {
+        let url = format!("{}/vcek/v1/{product}/crl", self.base_url);
+        let res = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Fetch CRL from KDS failed: {e}"))?;
+        match res.status() {
+            reqwest::StatusCode::OK => Ok(Some(res.bytes().await?.to_vec())),
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status => bail!("Fetch CRL from KDS failed: {status}"),
+        }
+    }
+
+    async fn fetch_asvk(&self, product: &str) -> Result<Option<Vec<u8>>> {
+        // The chain is PEM encoded, with the ASVK followed by the ARK.
+        let url = format!("{}/vlek/v1/{product}/cert_chain", self.base_url);
+        let res = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Fetch ASVK from KDS failed: {e}"))?;
+        match res.status() {
+            reqwest::StatusCode::OK => {
+                let chain = X509::stack_from_pem(&res.bytes().await?)
+                    .context("Malformed fetched ASVK/ARK")?;
+                let asvk = chain
+                    .first()
+                    .ok_or_else(|| anyhow!("Malformed fetched ASVK/ARK"))?;
+                Ok(Some(asvk.to_der()?))
+            }
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status => bail!("Fetch ASVK from KDS failed: {status}"),
+        }
+    }
+}
+
+/// Provides the VCEKs and VLEKs from the cache directory, or from the fetcher
+/// if they are not cached.
+pub struct CertificateProvider {
+    cache_dir: PathBuf,
+    fetcher: Option<Box<dyn CertificateFetcher + Send + Sync>>,
+}
+
+impl CertificateProvider {
+    pub fn new(config: &SnpConfig, work_dir: &Path) -> Self {
+        let cache_dir = config
+            .cert_cache_dir
+            .clone()
+            .unwrap_or_else(|| work_dir.join(DEFAULT_CACHE_DIR));
+        let fetcher = config
+            .kds_url
+            .as_deref()
+            .map(|url| Box::new(KdsFetcher::new(url)) as Box<dyn CertificateFetcher + Send + Sync>);
+
+        Self { cache_dir, fetcher }
+    }
+
+    pub fn with_fetcher(
+        cache_dir: PathBuf,
+        fetcher: Option<Box<dyn CertificateFetcher + Send + Sync>>,
+    ) -> Self {
+        Self { cache_dir, fetcher }
+    }
+
+    /// The path of a cached VCEK, which is specific to the chip and the TCB.
+    fn vcek_path(&self, key: &KeyId) -> PathBuf {
+        self.cache_dir.join("vcek").join(format!(
+            "{}-{}.der",
+            hex::encode(key.chip_id),
+            key.tcb_hex()
+        ))
+    }
+
+    /// Get the certificates which may sign the report of the key. The VCEK is
+    /// specific to the chip, and fetched if it is not cached. A VLEK is shared
+    /// by the chips of a cloud provider, so the VLEKs of all the providers for
+    /// the product and TCB are returned, and the caller picks the one which
+    /// signs the report.
+    pub async fn get(&self, key: &KeyId) -> Result<Vec<X509>> {
+        match key.signing_key {
+            SigningKey::Vcek => Ok(vec![self.get_vcek(key).await?]),
+            SigningKey::Vlek => self.get_vleks(key).await,
+        }
+    }
+
+    async fn get_vcek(&self, key: &KeyId) -> Result<X509> {
+        let path = self.vcek_path(key);
+        if let Ok(der) = tokio::fs::read(&path).await {
+            return X509::from_der(&der)
+                .with_context(|| format!("Malformed cached certificate {}", path.display()));
+        }
+
+        let der = match &self.fetcher {
+            Some(fetcher) => fetcher.fetch(key).await?,
+            None => None,
+        }
+        .ok_or_else(|| anyhow!("{:?} not found.", key.signing_key))?;
+        let cert = X509::from_der(&der).context("Malformed fetched certificate")?;
+
+        // The certificate is verified against the AMD chain by the caller, so
+        // a bogus certificate in the cache cannot be used to forge reports.
+        if let Err(e) = self.store(&path, &der).await {
+            warn!("Cannot cache {}: {e}", path.display());
+        }
+
+        Ok(cert)
+    }
+
+    async fn get_vleks(&self, key: &KeyId) -> Result<Vec<X509>> {
+        let mut vleks = vec![];
+        if let Ok(mut providers) = tokio::fs::read_dir(self.cache_dir.join("vlek")).await {
+            while let Some(provider) = providers.next_entry().await? {
+                for product in key.products() {
+                    let path = provider
+                        .path()
+                        .join(format!("{product}-{}.der", key.tcb_hex()));
+                    if let Ok(der) = tokio::fs::read(&path).await {
+                        vleks.push(X509::from_der(&der).with_context(|| {
+                            format!("Malformed cached certificate {}", path.display())
+                        })?);
+                    }
+                }
+            }
+        }
+
+        if vleks.is_empty() {
+            bail!("{:?} not found.", key.signing_key);
+        }
+
+        Ok(vleks)
+    }
+
+    /// Get the ASVK of the product, which issues the VLEKs, from the cache or
+    /// else from the fetcher. It is verified against the trusted ARKs by the
+    /// caller, like the VCEK.
+    pub async fn get_asvk(&self, product: &str) -> Result<Option<X509>> {
+        let path = self.cache_dir.join("asvk").join(format!("{product}.der"));
+        if let Ok(der) = tokio::fs::read(&path).await {
+            return X509::from_der(&der)
+                .map(Some)
+                .with_context(|| format!("Malformed cached ASVK {}", path.display()));
+        }
+
+        let der = match &self.fetcher {
+            Some(fetcher) => fetcher.fetch_asvk(product).await?,
+            None => None,
+        };
+        let Some(der) = der else {
+            return Ok(None);
+        };
+        let asvk = X509::from_der(&der).context("Malformed fetched ASVK")?;
+        if let Err(e) = self.store(&path, &der).await {
+            warn!("Cannot cache {}: {e}", path.display());
+        }
+
+        Ok(Some(asvk))
+    }
+
+    /// Get the CRL of the product from the cache. An outdated or missing CRL
+    /// is fetched again, and the outdated one is still used if the fetch
+    /// fails, s.t. its freshness is reported by the caller.
+    pub async fn get_crl(&self, product: &str) -> Result<Option<X509Crl>> {
+        let path = self.cache_dir.join("crl").join(format!("{product}.crl"));
+        let cached = match tokio::fs::read(&path).await {
+            Ok(der) => Some(
+                load_crl(&der)
+                    .with_context(|| format!("Malformed cached CRL {}", path.display()))?,
+            ),
+            Err(_) => None,
+        };
+        if let Some(crl) = &cached {
+            if is_fresh(crl)? {
+                return Ok(cached);
+            }
+        }
+
+        let Some(fetcher) = &self.fetcher else {
+            return Ok(cached);
+        };
+        let der = match fetcher.fetch_crl(product).await {
+            Ok(Some(der)) => der,
+            Ok(None) => return Ok(cached),
+            Err(e) if cached.is_some() => {
+                warn!("Use outdated CRL of {product}: {e}");
+                return Ok(cached);
+            }
+            Err(e) => return Err(e),
+        };
+        let crl = load_crl(&der).context("Malformed fetched CRL")?;
+        if let Err(e) = self.store(&path, &der).await {
+            warn!("Cannot cache {}: {e}", path.display());
+        }
+
+        Ok(Some(crl))
+    }
+
+    /// Write the certificate to a temporary file first, which then replaces
+    /// the cached file, s.t. concurrent readers never see a partial file.
+    async fn store(&self, path: &Path, der: &[u8]) -> Result<()> {
+        let dir = path.parent().ok_or_else(|| anyhow!("Illegal cache path"))?;
+        tokio::fs::create_dir_all(dir).await?;
+        let temp_path = dir.join(format!(".{}.tmp", uuid::Uuid::new_v4()));
+        tokio::fs::write(&temp_path, der).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    use crate::verifier::amd::common_name;
+
+    const VCEK: &[u8] = include_bytes!("../snp/test-vcek.der");
+    const VLEK: &[u8] = include_bytes!("../snp/test-vlek.der");
+
+    fn key_id(signing_key: SigningKey) -> KeyId {
+        let mut tcb = TcbVersion::default();
+        tcb.bootloader = 3;
+        tcb.snp = 8;
+        tcb.microcode = 115;
+
+        KeyId {
+            signing_key,
+            chip_id: [0xab; 64],
+            tcb,
+            product: Some(ProcessorGeneration::Milan),
+        }
+    }
+
+    /// Serve one HTTP request with the body, and return the requested path.
+    fn serve(body: &'static [u8]) -> (String, std::thread::JoinHandle<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let len = stream.read(&mut request).unwrap();
+            let request = String::from_utf8_lossy(&request[..len]).to_string();
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+
+            request.split_whitespace().nth(1).unwrap().to_string()
+        });
+
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_get_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = CertificateProvider::with_fetcher(dir.path().to_path_buf(), None);
+        let key = key_id(SigningKey::Vcek);
+        assert!(provider.get(&key).await.is_err());
+
+        provider
+            .store(&provider.vcek_path(&key), VCEK)
+            .await
+            .unwrap();
+        let certs = provider.get(&key).await.unwrap();
+        assert_eq!(certs.len(), 1);
+        assert_eq!(certs[0].to_der().unwrap(), VCEK);
+    }
+
+    #[tokio::test]
+    async fn test_get_cached_vleks() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = CertificateProvider::with_fetcher(dir.path().to_path_buf(), None);
+        let key = key_id(SigningKey::Vlek);
+        assert!(provider.get(&key).await.is_err());
+
+        // The VLEKs of two providers for the same TCB, and one for Genoa.
+        let vlek_dir = dir.path().join("vlek");
+        for path in [
+            "provider-a/Milan-03000873.der",
+            "provider-b/Milan-03000873.der",
+            "provider-b/Genoa-03000873.der",
+        ] {
+            provider.store(&vlek_dir.join(path), VLEK).await.unwrap();
+        }
+        assert_eq!(provider.get(&key).await.unwrap().len(), 2);
+
+        let key = KeyId {
+            product: None,
+            ..key
+        };
+        assert_eq!(provider.get(&key).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_and_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, server) = serve(VCEK);
+        let provider = CertificateProvider::with_fetcher(
+            dir.path().to_path_buf(),
+            Some(Box::new(KdsFetcher::new(&url))),
+        );
+        let key = key_id(SigningKey::Vcek);
+
+        assert_eq!(provider.get(&key).await.unwrap()[0].to_der().unwrap(), VCEK);
+        let path = server.join().unwrap();
+        assert_eq!(
+            path,
+            format!(
+                "/vcek/v1/Milan/{}?blSPL=3&teeSPL=0&snpSPL=8&ucodeSPL=115",
+                "ab".repeat(64)
+            )
+        );
+
+        // The server is gone, so the VCEK must come from the cache.
+        assert_eq!(provider.get(&key).await.unwrap()[0].to_der().unwrap(), VCEK);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_asvk() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = CertificateProvider::with_fetcher(dir.path().to_path_buf(), None);
+        assert!(provider.get_asvk("Lab").await.unwrap().is_none());
+
+        let (url, server) = serve(include_bytes!("test-lab-asvk.pem"));
+        let provider = CertificateProvider::with_fetcher(
+            dir.path().to_path_buf(),
+            Some(Box::new(KdsFetcher::new(&url))),
+        );
+        let asvk = provider.get_asvk("Lab").await.unwrap().unwrap();
+        assert_eq!(common_name(asvk.subject_name()).unwrap(), "SEV-VLEK-Lab");
+        assert_eq!(server.join().unwrap(), "/vlek/v1/Lab/cert_chain");
+
+        // The server is gone, so the ASVK must come from the cache.
+        assert!(provider.get_asvk("Lab").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_crl() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = CertificateProvider::with_fetcher(dir.path().to_path_buf(), None);
+        assert!(provider.get_crl("Test").await.unwrap().is_none());
+
+        let (url, server) = serve(include_bytes!("test-crl.pem"));
+        let provider = CertificateProvider::with_fetcher(
+            dir.path().to_path_buf(),
+            Some(Box::new(KdsFetcher::new(&url))),
+        );
+        assert!(provider.get_crl("Test").await.unwrap().is_some());
+        assert_eq!(server.join().unwrap(), "/vcek/v1/Test/crl");
+
+        // The CRL is fresh, so it must come from the cache.
+        assert!(provider.get_crl("Test").await.unwrap().is_some());
+    }
+}
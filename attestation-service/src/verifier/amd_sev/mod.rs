@@ -0,0 +1,344 @@
+//! Verifier of the launch measurement of AMD SEV and SEV-ES (pre-SNP) guests.
+//!
+//! SEV guests do not have attestation reports. Instead, the guest owner sets
+//! up a launch session with the platform, whose PDH is certified by the AMD
+//! certificate chain, and the firmware measures the launch as
+//! `HMAC-SHA256(TIK, 0x04 || API_MAJOR || API_MINOR || BUILD || POLICY || LD || MNONCE)`,
+//! where TIK is the integrity key of the session and LD the launch digest.
+//!
+//! The TIK is a secret of the guest owner, e.g. the KBS, so it is never taken
+//! from the evidence. Instead, the guest owner registers each launch session
+//! with the AS before the launch, as a JSON file in the session directory,
+//! named by the hex encoded SHA-256 digest of the nonce of the attestation.
+//! The session gives the TIK, the guest policy and the PDH of the platform,
+//! which were used to set up the launch. A session can be used only once, and
+//! evidence without a registered session is rejected, s.t. the evidence is
+//! bound to the nonce and cannot be replayed. The measurement cannot bind the
+//! TEE public key.
+
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+extern crate serde;
+use self::serde::Deserialize;
+use super::*;
+use async_trait::async_trait;
+use codicon::{Decoder, Encoder};
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, sign::Signer};
+use serde_json::json;
+use sev::certs::{
+    sev::{
+        builtin::{milan, naples, rome},
+        ca, Chain,
+    },
+    Verifiable,
+};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// The context of the LAUNCH_MEASURE command in the SEV API.
+const MEASUREMENT_CONTEXT: u8 = 0x04;
+const MEASURE_LEN: usize = 32;
+const MNONCE_LEN: usize = 16;
+
+/// The default session directory under the work dir of the AS.
+const DEFAULT_SESSION_DIR: &str = "sev/sessions";
+
+/// The guest policy bits in the SEV API.
+const POLICY_NODBG: u32 = 1 << 0;
+const POLICY_NOKS: u32 = 1 << 1;
+const POLICY_ES: u32 = 1 << 2;
+const POLICY_NOSEND: u32 = 1 << 3;
+const POLICY_DOMAIN: u32 = 1 << 4;
+const POLICY_SEV: u32 = 1 << 5;
+
+#[derive(Deserialize)]
+struct SevEvidence {
+    /// Base64 encoded certificate chain of the platform (PDH, PEK, OCA, CEK,
+    /// ASK and ARK), as exported by `sevctl export --full`.
+    cert_chain: String,
+
+    /// The firmware version reported by LAUNCH_START of the session.
+    api_major: u8,
+    api_minor: u8,
+    build: u8,
+
+    /// Base64 encoded launch digest of the guest, i.e. its measurement.
+    launch_digest: String,
+
+    /// Base64 encoded result of LAUNCH_MEASURE, i.e. MEASURE || MNONCE.
+    launch_measurement: String,
+}
+
+/// A launch session registered by the guest owner.
+#[derive(Deserialize)]
+struct SevSession {
+    /// Base64 encoded transport integrity key of the launch session.
+    tik: String,
+
+    /// The guest policy given to LAUNCH_START.
+    policy: u32,
+
+    /// Base64 encoded PDH certificate of the platform, which the session was
+    /// set up with.
+    pdh: String,
+}
+
+pub struct SevVerifier {
+    session_dir: PathBuf,
+}
+
+impl SevVerifier {
+    pub fn new(config: &SevConfig, work_dir: &Path) -> Self {
+        let session_dir = config
+            .session_dir
+            .clone()
+            .unwrap_or_else(|| work_dir.join(DEFAULT_SESSION_DIR));
+
+        Self { session_dir }
+    }
+
+    /// Take the launch session registered for the nonce. The session file is
+    /// removed, s.t. it cannot be used again.
+    async fn take_session(&self, nonce: &str) -> Result<SevSession> {
+        let path = self
+            .session_dir
+            .join(format!("{}.json", hex::encode(Sha256::digest(nonce))));
+        let session = tokio::fs::read(&path)
+            .await
+            .map_err(|_| anyhow!("No SEV launch session registered for the nonce"))?;
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| anyhow!("Take SEV launch session failed: {e}"))?;
+
+        serde_json::from_slice(&session).context("Deserialize SEV launch session failed.")
+    }
+}
+
+#[async_trait]
+impl Verifier for SevVerifier {
+    async fn evaluate(
+        &self,
+        nonce: String,
+        attestation: &Attestation,
+    ) -> Result<TeeEvidenceParsedClaim> {
+        let tee_evidence = serde_json::from_str::<SevEvidence>(&attestation.tee_evidence)
+            .context("Deserialize SEV evidence failed.")?;
+        let session = self.take_session(&nonce).await?;
+
+        verify_cert_chain(&tee_evidence.cert_chain, &session)?;
+        let launch_digest = verify_launch_measurement(&tee_evidence, &session)?;
+
+        Ok(parse_tee_evidence(&tee_evidence, &session, &launch_digest))
+    }
+}
+
+/// Verify the certificate chain of the platform up to one of the AMD ARKs of
+/// the SEV capable product generations, and check that its PDH is the one of
+/// the launch session.
+fn verify_cert_chain(cert_chain: &str, session: &SevSession) -> Result<()> {
+    let raw_chain = base64::engine::general_purpose::STANDARD
+        .decode(cert_chain)
+        .context("Base64 decode SEV cert chain failed.")?;
+    let chain = Chain::decode(&mut &raw_chain[..], ()).context("Malformed SEV cert chain")?;
+
+    let ark = [naples::ARK, rome::ARK, milan::ARK]
+        .iter()
+        .map(|ark| ca::Certificate::decode(&mut &ark[..], ()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .context("Malformed builtin ARK")?
+        .into_iter()
+        .find(|ark| (ark, &chain.ca.ask).verify().is_ok())
+        .ok_or_else(|| anyhow!("ASK is not signed by an AMD ARK"))?;
+
+    (&ark, &ark)
+        .verify()
+        .context("ARK cert Signature validation failed.")?;
+    (&chain.ca.ask, &chain.sev.cek)
+        .verify()
+        .context("CEK cert Signature validation failed.")?;
+    (&chain.sev.cek, &chain.sev.pek)
+        .verify()
+        .context("PEK cert Signature validation failed.")?;
+    (&chain.sev.oca, &chain.sev.pek)
+        .verify()
+        .context("PEK cert OCA Signature validation failed.")?;
+    (&chain.sev.pek, &chain.sev.pdh)
+        .verify()
+        .context("PDH cert Signature validation failed.")?;
+
+    let session_pdh = base64::engine::general_purpose::STANDARD
+        .decode(&session.pdh)
+        .context("Base64 decode session PDH failed.")?;
+    let mut pdh = vec![];
+    chain.sev.pdh.encode(&mut pdh, ())?;
+    if pdh != session_pdh {
+        bail!("PDH does not match the launch session");
+    }
+
+    Ok(())
+}
+
+/// Check the launch measurement with the TIK of the session, and return the
+/// launch digest which is measured.
+fn verify_launch_measurement(evidence: &SevEvidence, session: &SevSession) -> Result<Vec<u8>> {
+    let engine = base64::engine::general_purpose::STANDARD;
+    let launch_digest = engine
+        .decode(&evidence.launch_digest)
+        .context("Base64 decode launch digest failed.")?;
+    let launch_measurement = engine
+        .decode(&evidence.launch_measurement)
+        .context("Base64 decode launch measurement failed.")?;
+    let tik = engine
+        .decode(&session.tik)
+        .context("Base64 decode TIK failed.")?;
+
+    if launch_measurement.len() != MEASURE_LEN + MNONCE_LEN {
+        bail!("Unexpected launch measurement size");
+    }
+    let (measure, mnonce) = launch_measurement.split_at(MEASURE_LEN);
+
+    let key = PKey::hmac(&tik)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(&[
+        MEASUREMENT_CONTEXT,
+        evidence.api_major,
+        evidence.api_minor,
+        evidence.build,
+    ])?;
+    signer.update(&session.policy.to_le_bytes())?;
+    signer.update(&launch_digest)?;
+    signer.update(mnonce)?;
+    let expected_measure = signer.sign_to_vec()?;
+
+    if !memcmp::eq(&expected_measure, measure) {
+        bail!("Launch Measurement Mismatch");
+    }
+
+    Ok(launch_digest)
+}
+
+fn parse_tee_evidence(
+    evidence: &SevEvidence,
+    session: &SevSession,
+    launch_digest: &[u8],
+) -> TeeEvidenceParsedClaim {
+    let policy = session.policy;
+    let claims_map = json!({
+        // policy fields
+        "policy_nodbg": format!("{}", policy & POLICY_NODBG != 0),
+        "policy_noks": format!("{}", policy & POLICY_NOKS != 0),
+        "policy_es": format!("{}", policy & POLICY_ES != 0),
+        "policy_nosend": format!("{}", policy & POLICY_NOSEND != 0),
+        "policy_domain": format!("{}", policy & POLICY_DOMAIN != 0),
+        "policy_sev": format!("{}", policy & POLICY_SEV != 0),
+        "policy_api_major": format!("{}", (policy >> 16) & 0xff),
+        "policy_api_minor": format!("{}", (policy >> 24) & 0xff),
+
+        // versioning info
+        "api_major": format!("{}", evidence.api_major),
+        "api_minor": format!("{}", evidence.api_minor),
+        "build": format!("{}", evidence.build),
+
+        // measurement
+        "measurement": base64::engine::general_purpose::STANDARD.encode(launch_digest),
+    });
+
+    claims_map as TeeEvidenceParsedClaim
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(tik: &[u8]) -> SevSession {
+        SevSession {
+            tik: base64::engine::general_purpose::STANDARD.encode(tik),
+            policy: POLICY_NODBG | POLICY_ES,
+            pdh: String::new(),
+        }
+    }
+
+    fn evidence(session: &SevSession, launch_digest: &[u8], mnonce: &[u8]) -> SevEvidence {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let tik = engine.decode(&session.tik).unwrap();
+
+        let key = PKey::hmac(&tik).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer.update(&[0x04, 0, 24, 15]).unwrap();
+        signer.update(&session.policy.to_le_bytes()).unwrap();
+        signer.update(launch_digest).unwrap();
+        signer.update(mnonce).unwrap();
+        let mut launch_measurement = signer.sign_to_vec().unwrap();
+        launch_measurement.extend_from_slice(mnonce);
+
+        SevEvidence {
+            cert_chain: String::new(),
+            api_major: 0,
+            api_minor: 24,
+            build: 15,
+            launch_digest: engine.encode(launch_digest),
+            launch_measurement: engine.encode(launch_measurement),
+        }
+    }
+
+    #[test]
+    fn test_verify_launch_measurement() {
+        let session = session(&[1; 16]);
+        let evidence = evidence(&session, &[2; 32], &[3; 16]);
+        assert_eq!(
+            verify_launch_measurement(&evidence, &session).unwrap(),
+            vec![2; 32]
+        );
+
+        let claims = parse_tee_evidence(&evidence, &session, &[2; 32]);
+        assert_eq!(claims["policy_nodbg"], "true");
+        assert_eq!(claims["policy_noks"], "false");
+        assert_eq!(claims["policy_es"], "true");
+        assert_eq!(claims["build"], "15");
+    }
+
+    #[test]
+    fn test_verify_launch_measurement_mismatch() {
+        let session = session(&[1; 16]);
+        let mut tampered = evidence(&session, &[2; 32], &[3; 16]);
+        tampered.launch_digest = base64::engine::general_purpose::STANDARD.encode([4; 32]);
+        assert!(verify_launch_measurement(&tampered, &session).is_err());
+
+        // an attester cannot choose the TIK
+        let forged = evidence(&self::session(&[5; 16]), &[2; 32], &[3; 16]);
+        assert!(verify_launch_measurement(&forged, &session).is_err());
+
+        // nor the policy
+        let mut other_policy = self::session(&[1; 16]);
+        other_policy.policy = POLICY_ES;
+        let evidence = evidence(&other_policy, &[2; 32], &[3; 16]);
+        assert!(verify_launch_measurement(&evidence, &session).is_err());
+    }
+
+    #[test]
+    fn test_verify_invalid_cert_chain() {
+        let session = session(&[1; 16]);
+        assert!(verify_cert_chain("", &session).is_err());
+        assert!(verify_cert_chain("not base64", &session).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_take_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SevConfig {
+            session_dir: Some(dir.path().to_path_buf()),
+        };
+        let verifier = SevVerifier::new(&config, Path::new("/nonexistent"));
+        assert!(verifier.take_session("nonce").await.is_err());
+
+        let path = dir
+            .path()
+            .join(format!("{}.json", hex::encode(Sha256::digest("nonce"))));
+        std::fs::write(&path, r#"{"tik": "AQEB", "policy": 1, "pdh": ""}"#).unwrap();
+        assert!(verifier.take_session("other nonce").await.is_err());
+        assert_eq!(verifier.take_session("nonce").await.unwrap().policy, 1);
+
+        // a session can be used only once
+        assert!(verifier.take_session("nonce").await.is_err());
+    }
+}
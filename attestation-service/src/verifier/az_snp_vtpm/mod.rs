@@ -3,20 +3,17 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use super::{Attestation, TeeEvidenceParsedClaim, Verifier};
+use super::amd::{claims::parse_report, TrustedChains};
+use super::{Attestation, SnpConfig, TeeEvidenceParsedClaim, Verifier};
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use az_snp_vtpm::certs::{AmdChain, Vcek, X509};
 use az_snp_vtpm::hcl::HclData;
 use az_snp_vtpm::report::Validateable;
 use az_snp_vtpm::vtpm::{Quote, VerifyVTpmQuote};
-use base64::Engine;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use sev::firmware::guest::AttestationReport;
-use sev::firmware::host::TcbVersion;
 use sha2::{Digest, Sha384};
-use std::collections::BTreeMap;
 
 const HCL_VMPL_VALUE: u32 = 0;
 
@@ -27,8 +24,17 @@ struct Evidence {
     vcek: String,
 }
 
-#[derive(Default)]
-pub struct AzSnpVtpm;
+pub struct AzSnpVtpm {
+    chains: TrustedChains,
+}
+
+impl AzSnpVtpm {
+    pub fn new(config: &SnpConfig) -> Result<Self> {
+        Ok(Self {
+            chains: TrustedChains::new(config)?,
+        })
+    }
+}
 
 #[async_trait]
 impl Verifier for AzSnpVtpm {
@@ -43,15 +49,16 @@ impl Verifier for AzSnpVtpm {
         let hcl_data: HclData = evidence.report[..].try_into()?;
         let snp_report = hcl_data.report().snp_report();
         let vcek = Vcek::from_pem(&evidence.vcek)?;
+        let amd_chain = build_amd_chain(&self.chains, &evidence.vcek)?;
 
         let hashed_quote = nonced_pub_key_hash(attestation, &nonce);
 
         verify_quote(&evidence.quote, &hcl_data, &hashed_quote)?;
-        verify_snp_report(snp_report, &vcek)?;
+        verify_snp_report(snp_report, &vcek, &amd_chain)?;
         let var_data = hcl_data.var_data();
         hcl_data.report().verify_report_data(var_data)?;
 
-        let claim = parse_tee_evidence(snp_report);
+        let claim = parse_report(snp_report);
         Ok(claim)
     }
 }
@@ -66,18 +73,22 @@ fn verify_quote(quote: &Quote, hcl_data: &HclData, hashed_nonce: &[u8]) -> Resul
     Ok(())
 }
 
-fn build_amd_chain() -> Result<AmdChain> {
-    let bytes = include_bytes!("./milan_ask_ark.pem");
-    let certs = X509::stack_from_pem(bytes)?;
-    let ask = certs[0].clone();
-    let ark = certs[1].clone();
-    let chain = AmdChain { ask, ark };
-    Ok(chain)
-}
+/// Build the AMD chain of the product generation which issued the VCEK.
+fn build_amd_chain(chains: &TrustedChains, vcek: &str) -> Result<AmdChain> {
+    let vcek = X509::from_pem(vcek.as_bytes()).context("Failed to load VCEK")?;
+    let chain = chains.chain_of(&vcek, None, None)?;
 
-fn verify_snp_report(snp_report: &AttestationReport, vcek: &Vcek) -> Result<()> {
-    let amd_chain = build_amd_chain()?;
+    Ok(AmdChain {
+        ask: chain.ask,
+        ark: chain.ark,
+    })
+}
 
+fn verify_snp_report(
+    snp_report: &AttestationReport,
+    vcek: &Vcek,
+    amd_chain: &AmdChain,
+) -> Result<()> {
     amd_chain
         .validate()
         .context("Verification of AMD certificate chain failed")?;
@@ -94,45 +105,6 @@ fn verify_snp_report(snp_report: &AttestationReport, vcek: &Vcek) -> Result<()>
     Ok(())
 }
 
-fn parse_tee_evidence(report: &AttestationReport) -> TeeEvidenceParsedClaim {
-    let TcbVersion {
-        bootloader,
-        tee,
-        snp,
-        microcode,
-        ..
-    } = report.reported_tcb;
-    let policy = report.policy;
-
-    let num_values = [
-        ("policy_abi_major", policy.abi_major()),
-        ("policy_abi_minor", policy.abi_minor()),
-        ("policy_smt_allowed", policy.smt_allowed()),
-        ("policy_migrate_ma", policy.migrate_ma_allowed()),
-        ("policy_debug_allowed", policy.debug_allowed()),
-        ("policy_single_socket", policy.single_socket_required()),
-        // versioning info
-        ("reported_tcb_bootloader", bootloader as u64),
-        ("reported_tcb_tee", tee as u64),
-        ("reported_tcb_snp", snp as u64),
-        ("reported_tcb_microcode", microcode as u64),
-        // platform info
-        ("platform_tsme_enabled", report.plat_info.tsme_enabled()),
-        ("platform_smt_enabled", report.plat_info.smt_enabled()),
-    ];
-
-    let mut string_map: BTreeMap<_, _> = num_values
-        .iter()
-        .map(|(k, v)| (*k, v.to_string()))
-        .collect();
-    string_map.insert(
-        "measurement",
-        base64::engine::general_purpose::STANDARD.encode(report.measurement),
-    );
-
-    json!(string_map) as TeeEvidenceParsedClaim
-}
-
 fn nonced_pub_key_hash(attestation: &Attestation, nonce: &str) -> Vec<u8> {
     let mut hasher = Sha384::new();
     hasher.update(nonce);
@@ -144,19 +116,23 @@ fn nonced_pub_key_hash(attestation: &Attestation, nonce: &str) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
     #[test]
     fn test_verify_snp_report() {
         let report = include_bytes!("../../../../test_data/az-hcl-data.bin");
         let hcl_data: HclData = report.as_slice().try_into().unwrap();
-        let vcek = Vcek::from_pem(include_str!("../../../../test_data/az-vcek.pem")).unwrap();
-        verify_snp_report(hcl_data.report().snp_report(), &vcek).unwrap();
+        let vcek_pem = include_str!("../../../../test_data/az-vcek.pem");
+        let vcek = Vcek::from_pem(vcek_pem).unwrap();
+        let chains = TrustedChains::new(&SnpConfig::default()).unwrap();
+        let amd_chain = build_amd_chain(&chains, vcek_pem).unwrap();
+        verify_snp_report(hcl_data.report().snp_report(), &vcek, &amd_chain).unwrap();
 
         let mut wrong_report = *report;
         // messing with snp report
         wrong_report[0x00b0] = 0;
         let wrong_hcl_data: HclData = wrong_report.as_slice().try_into().unwrap();
-        verify_snp_report(wrong_hcl_data.report().snp_report(), &vcek).unwrap_err();
+        verify_snp_report(wrong_hcl_data.report().snp_report(), &vcek, &amd_chain).unwrap_err();
     }
 
     #[test]
@@ -193,9 +169,28 @@ mod tests {
         let report = include_bytes!("../../../../test_data/az-hcl-data.bin");
         let hcl_data: HclData = report.as_slice().try_into().unwrap();
         let snp_report = hcl_data.report().snp_report();
-        let claim = parse_tee_evidence(snp_report);
+        let claim = parse_report(snp_report);
 
         let reference = json!({
+          "author_key_digest": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+          "chip_id": "w4Qnow1MevnZb3oVuXJpglpky3aiNS/9XRgRXYmtRz+OjAvNml2ShmErrUqt+0QmIFo7nk/qgjARNaFw5HdSTg==",
+          "committed_tcb_bootloader": "3",
+          "committed_tcb_microcode": "115",
+          "committed_tcb_snp": "8",
+          "committed_tcb_tee": "0",
+          "current_tcb_bootloader": "3",
+          "current_tcb_microcode": "206",
+          "current_tcb_snp": "8",
+          "current_tcb_tee": "0",
+          "family_id": "AQAAAAAAAAAAAAAAAAAAAA==",
+          "guest_svn": "4",
+          "host_data": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+          "id_key_digest": "A1YhWIKoJSeahbMAsLdCkx0RO/fjLd4uUP/efsdDykkezdfzNtwopuCyu1evekSj",
+          "image_id": "AgAAAAAAAAAAAAAAAAAAAA==",
+          "launch_tcb_bootloader": "3",
+          "launch_tcb_microcode": "115",
+          "launch_tcb_snp": "8",
+          "launch_tcb_tee": "0",
           "measurement": "ofOTBBMke7OM/BcVeeo8EtX+SQHwx5L2P9ddmPHvgnwjUAZE4OaS5r6Rf5BQ09OM",
           "platform_smt_enabled": "0",
           "platform_tsme_enabled": "1",
@@ -205,6 +200,7 @@ mod tests {
           "policy_migrate_ma": "0",
           "policy_single_socket": "0",
           "policy_smt_allowed": "1",
+          "report_id": "OF66gSFt5HdlSPy4b46tA8HryStiB/MhDZzOu4nJkAU=",
           "reported_tcb_bootloader": "3",
           "reported_tcb_microcode": "115",
           "reported_tcb_snp": "8",
@@ -2,15 +2,27 @@ use anyhow::*;
 use as_types::TeeEvidenceParsedClaim;
 use async_trait::async_trait;
 use kbs_types::{Attestation, Tee};
+use serde::Deserialize;
+use serde_variant::to_variant_name;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::config::Config;
 
 pub mod sample;
 
+#[cfg(feature = "sev-verifier")]
+pub mod amd_sev;
+
 #[cfg(feature = "az-snp-vtpm-verifier")]
 pub mod az_snp_vtpm;
 
 #[cfg(feature = "snp-verifier")]
 pub mod snp;
 
+#[cfg(any(feature = "snp-verifier", feature = "az-snp-vtpm-verifier"))]
+pub mod amd;
+
 #[cfg(feature = "tdx-verifier")]
 pub mod tdx;
 
@@ -23,15 +35,65 @@ pub mod csv;
 #[cfg(feature = "cca-verifier")]
 pub mod cca;
 
-pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>> {
+/// Configuration of the AMD SEV verifier.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SevConfig {
+    /// Directory of the launch sessions registered by the guest owner.
+    /// Defaults to `sev/sessions` under the work dir.
+    #[serde(default)]
+    pub session_dir: Option<PathBuf>,
+}
+
+/// Configuration of the AMD SEV-SNP verifiers.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SnpConfig {
+    /// PEM files of AMD certificate chains trusted in addition to the bundled
+    /// ones, each with the ASK or ASVK followed by the ARK.
+    #[serde(default)]
+    pub trusted_chains: Vec<PathBuf>,
+
+    /// Directory of the cached VCEKs and VLEKs, used for the evidence without
+    /// the certificate. Defaults to `snp/certs` under the work dir.
+    #[serde(default)]
+    pub cert_cache_dir: Option<PathBuf>,
+
+    /// Base URL of the AMD KDS, e.g. `https://kdsintf.amd.com`, to fetch the
+    /// VCEKs and ASVKs missing in the cache. If not set, nothing is fetched.
+    #[serde(default)]
+    pub kds_url: Option<String>,
+
+    /// PEM or DER files of AMD CRLs, each used for the chain whose ARK issues
+    /// it. For the other chains, the CRL is taken from `crl/` in the cache
+    /// directory, and fetched from the KDS if `kds_url` is set.
+    #[serde(default)]
+    pub crls: Vec<PathBuf>,
+
+    /// Reject the evidence if no CRL is found for its chain. Otherwise the
+    /// revocation check is skipped, which is reported in the claims.
+    #[serde(default)]
+    pub require_crl: bool,
+}
+
+pub(crate) fn to_verifier(
+    tee: &Tee,
+    #[allow(unused_variables)] config: &Config,
+) -> Result<Box<dyn Verifier + Send + Sync>> {
     match tee {
-        Tee::Sev => todo!(),
+        Tee::Sev => {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "sev-verifier")] {
+                    Ok(Box::new(amd_sev::SevVerifier::new(&config.sev, &config.work_dir)) as Box<dyn Verifier + Send + Sync>)
+                } else {
+                    anyhow::bail!("feature `sev-verifier` is not enabled!");
+                }
+            }
+        }
         Tee::AzSnpVtpm => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "az-snp-vtpm-verifier")] {
-                    Ok(Box::<az_snp_vtpm::AzSnpVtpm>::default() as Box<dyn Verifier + Send + Sync>)
+                    Ok(Box::new(az_snp_vtpm::AzSnpVtpm::new(&config.snp)?) as Box<dyn Verifier + Send + Sync>)
                 } else {
-                    todo!()
+                    anyhow::bail!("feature `az-snp-vtpm-verifier` is not enabled!");
                 }
             }
         }
@@ -40,16 +102,16 @@ pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>>
                 if #[cfg(feature = "tdx-verifier")] {
                     Ok(Box::<tdx::Tdx>::default() as Box<dyn Verifier + Send + Sync>)
                 } else {
-                    todo!()
+                    anyhow::bail!("feature `tdx-verifier` is not enabled!");
                 }
             }
         }
         Tee::Snp => {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "snp-verifier")] {
-                    Ok(Box::<snp::Snp>::default() as Box<dyn Verifier + Send + Sync>)
+                    Ok(Box::new(snp::Snp::new(&config.snp, &config.work_dir)?) as Box<dyn Verifier + Send + Sync>)
                 } else {
-                    bail!("SNP Verifier not enabled.")
+                    anyhow::bail!("feature `snp-verifier` is not enabled!");
                 }
             }
         }
@@ -86,6 +148,59 @@ pub(crate) fn to_verifier(tee: &Tee) -> Result<Box<dyn Verifier + Send + Sync>>
     }
 }
 
+/// All the TEEs, whose verifiers are built if their features are enabled.
+const TEES: [Tee; 8] = [
+    Tee::Sev,
+    Tee::AzSnpVtpm,
+    Tee::Tdx,
+    Tee::Snp,
+    Tee::Sample,
+    Tee::Sgx,
+    Tee::Csv,
+    Tee::Cca,
+];
+
+fn is_enabled(tee: &Tee) -> bool {
+    match tee {
+        Tee::Sev => cfg!(feature = "sev-verifier"),
+        Tee::AzSnpVtpm => cfg!(feature = "az-snp-vtpm-verifier"),
+        Tee::Tdx => cfg!(feature = "tdx-verifier"),
+        Tee::Snp => cfg!(feature = "snp-verifier"),
+        Tee::Sample => true,
+        Tee::Sgx => cfg!(feature = "sgx-verifier"),
+        Tee::Csv => cfg!(feature = "csv-verifier"),
+        Tee::Cca => cfg!(feature = "cca-verifier"),
+    }
+}
+
+/// The verifiers of the enabled TEEs. They are built once when the AS is
+/// created, s.t. a bad config fails at startup, and the state of a verifier,
+/// e.g. the KDS client and the certificate cache of the SEV-SNP verifier, is
+/// shared by all the evaluations.
+pub(crate) struct Verifiers(HashMap<&'static str, Box<dyn Verifier + Send + Sync>>);
+
+impl Verifiers {
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut verifiers = HashMap::new();
+        for tee in TEES.iter().filter(|tee| is_enabled(tee)) {
+            let name = to_variant_name(tee)?;
+            let verifier = to_verifier(tee, config)
+                .with_context(|| format!("Create {name} verifier failed"))?;
+            verifiers.insert(name, verifier);
+        }
+
+        Ok(Self(verifiers))
+    }
+
+    pub fn get(&self, tee: &Tee) -> Result<&(dyn Verifier + Send + Sync)> {
+        let name = to_variant_name(tee)?;
+        self.0
+            .get(name)
+            .map(|verifier| verifier.as_ref())
+            .ok_or_else(|| anyhow!("the verifier of `{name}` is not enabled!"))
+    }
+}
+
 #[async_trait]
 pub trait Verifier {
     /// Verify the hardware signature and report data in TEE quote.
@@ -97,3 +212,22 @@ pub trait Verifier {
         attestation: &Attestation,
     ) -> Result<TeeEvidenceParsedClaim>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verifiers() {
+        let verifiers = Verifiers::new(&Config::default()).unwrap();
+        assert!(verifiers.get(&Tee::Sample).is_ok());
+    }
+
+    #[cfg(feature = "snp-verifier")]
+    #[test]
+    fn test_verifiers_bad_config() {
+        let mut config = Config::default();
+        config.snp.crls = vec!["/nonexistent/crl.pem".into()];
+        assert!(Verifiers::new(&config).is_err());
+    }
+}
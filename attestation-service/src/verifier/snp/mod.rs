@@ -1,27 +1,32 @@
 use anyhow::{anyhow, Context, Result};
-use base64::Engine;
 extern crate serde;
 use self::serde::{Deserialize, Serialize};
 use super::*;
 use asn1_rs::{oid, Integer, OctetString, Oid};
 use async_trait::async_trait;
 use kbs_types::TeePubKey;
-use openssl::{
-    ec::EcKey,
-    ecdsa,
-    pkey::{PKey, Public},
-    x509,
-};
-use serde_json::json;
+use openssl::{ec::EcKey, ecdsa, x509};
 use sev::firmware::guest::AttestationReport;
 use sev::firmware::host::{CertTableEntry, CertType};
 use sha2::{Digest, Sha384};
+use std::path::Path;
 use x509_parser::prelude::*;
 
+use super::amd::claims::parse_report;
+use super::amd::crl::{revocation_claims, RevocationChecker, RevocationStatus};
+use super::amd::provider::{CertificateProvider, KeyId, SigningKey};
+use super::amd::{common_name, vlek_product, AmdChain, ProcessorGeneration, TrustedChains};
+
 #[derive(Serialize, Deserialize)]
 struct SnpEvidence {
     attestation_report: AttestationReport,
+    /// The certificates given by the host. If the VCEK or VLEK is missing, it
+    /// is resolved by the certificate provider.
     cert_chain: Vec<CertTableEntry>,
+    /// The product generation of the host. If not given, it is detected from
+    /// the issuer of the VCEK.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    product: Option<ProcessorGeneration>,
 }
 
 const HW_ID_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .4);
@@ -30,8 +35,24 @@ const SNP_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .3);
 const TEE_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .2);
 const LOADER_SPL_OID: Oid<'static> = oid!(1.3.6 .1 .4 .1 .3704 .1 .3 .1);
 
-#[derive(Debug, Default)]
-pub struct Snp {}
+/// Offset of the report field whose bits 4:2 select the signing key.
+const SIGNING_KEY_OFFSET: usize = 0x48;
+
+pub struct Snp {
+    chains: TrustedChains,
+    provider: CertificateProvider,
+    revocation: RevocationChecker,
+}
+
+impl Snp {
+    pub fn new(config: &SnpConfig, work_dir: &Path) -> Result<Self> {
+        Ok(Self {
+            chains: TrustedChains::new(config)?,
+            provider: CertificateProvider::new(config, work_dir),
+            revocation: RevocationChecker::new(config)?,
+        })
+    }
+}
 
 #[async_trait]
 impl Verifier for Snp {
@@ -43,7 +64,13 @@ impl Verifier for Snp {
         let tee_evidence = serde_json::from_str::<SnpEvidence>(&attestation.tee_evidence)
             .context("Deserialize Quote failed.")?;
 
-        verify_report_signature(&tee_evidence)?;
+        let revocation_status = verify_report_signature(
+            &tee_evidence,
+            &self.chains,
+            &self.provider,
+            &self.revocation,
+        )
+        .await?;
 
         let report = tee_evidence.attestation_report;
         if report.version != 2 {
@@ -59,7 +86,7 @@ impl Verifier for Snp {
             return Err(anyhow!("Report Data Mismatch"));
         }
 
-        Ok(parse_tee_evidence(&report))
+        Ok(parse_tee_evidence(&report, revocation_status.as_ref()))
     }
 }
 
@@ -97,9 +124,65 @@ fn get_oid_int(vcek: &x509_parser::certificate::TbsCertificate, oid: Oid) -> Res
     val_int.as_u8().context("Unexpected data size")
 }
 
-fn verify_report_signature(evidence: &SnpEvidence) -> Result<()> {
-    // check cert chain
-    let vcek = verify_cert_chain(&evidence.cert_chain)?;
+fn signing_key(report: &[u8]) -> Result<SigningKey> {
+    let field = report
+        .get(SIGNING_KEY_OFFSET..SIGNING_KEY_OFFSET + 4)
+        .ok_or_else(|| anyhow!("Malformed report"))?;
+    let field = u32::from_le_bytes(field.try_into()?);
+
+    match (field >> 2) & 0b111 {
+        0 => Ok(SigningKey::Vcek),
+        1 => Ok(SigningKey::Vlek),
+        key => Err(anyhow!("Unsupported signing key {key}")),
+    }
+}
+
+async fn verify_report_signature(
+    evidence: &SnpEvidence,
+    chains: &TrustedChains,
+    provider: &CertificateProvider,
+    revocation: &RevocationChecker,
+) -> Result<Option<RevocationStatus>> {
+    let report = &evidence.attestation_report;
+    let report_bytes = bincode::serialize(report)?;
+    let signing_key = signing_key(&report_bytes)?;
+    let sig = ecdsa::EcdsaSig::try_from(&evidence.attestation_report.signature)?;
+    let data = &report_bytes[..=0x29f];
+
+    // get the signing key from the evidence, or from the provider
+    let vcek = match find_signing_cert(&evidence.cert_chain, signing_key)? {
+        Some(vcek) => vcek,
+        None => {
+            let key = KeyId {
+                signing_key,
+                chip_id: report.chip_id,
+                tcb: report.reported_tcb,
+                product: evidence.product,
+            };
+            provider
+                .get(&key)
+                .await?
+                .into_iter()
+                .find(|cert| signs_report(cert, &sig, data))
+                .ok_or_else(|| anyhow!("No {signing_key:?} found which signs the report"))?
+        }
+    };
+
+    // get the ASVK of a VLEK from the evidence, or from the provider
+    let asvk = match signing_key {
+        SigningKey::Vcek => None,
+        SigningKey::Vlek => match find_asvk(&evidence.cert_chain) {
+            Some(asvk) => Some(asvk),
+            None => get_asvk(&vcek, provider).await,
+        },
+    };
+
+    // check cert chain, and that the ASK or ASVK is not revoked
+    let chain = verify_cert_chain(&vcek, chains, evidence.product, asvk.as_ref())?;
+    let revocation_status = revocation
+        .check(&chain, provider)
+        .await
+        .context("Revocation check failed")?;
 
     // OpenSSL bindings do not expose custom extensions
     // Parse the vcek using x509_parser
@@ -107,8 +190,10 @@ fn verify_report_signature(evidence: &SnpEvidence) -> Result<()> {
     let parsed_vcek = X509Certificate::from_der(vcek_der)?.1.tbs_certificate;
 
     // verify vcek fields
-    // chip id
-    if get_oid_octets::<64>(&parsed_vcek, HW_ID_OID)? != evidence.attestation_report.chip_id {
+    // chip id, which a VLEK does not have
+    if signing_key == SigningKey::Vcek
+        && get_oid_octets::<64>(&parsed_vcek, HW_ID_OID)? != evidence.attestation_report.chip_id
+    {
         return Err(anyhow!("Chip ID mismatch"));
     }
 
@@ -135,47 +220,72 @@ fn verify_report_signature(evidence: &SnpEvidence) -> Result<()> {
     }
 
     // verify report signature
-    let sig = ecdsa::EcdsaSig::try_from(&evidence.attestation_report.signature)?;
-    let data = &bincode::serialize(&evidence.attestation_report)?[..=0x29f];
-
     sig.verify(data, EcKey::try_from(vcek.public_key()?)?.as_ref())
         .context("Signature validation failed.")?;
 
-    Ok(())
+    Ok(revocation_status)
 }
 
-fn load_milan_cert_chain() -> Result<(x509::X509, x509::X509)> {
-    let certs = x509::X509::stack_from_pem(include_bytes!("milan_ask_ark.pem"))?;
-    if certs.len() != 2 {
-        bail!("Malformed Milan ASK/ARK");
+/// Find the VCEK or VLEK in the certificates of the evidence. The VLEK is
+/// found by its issuer, which is the ASVK of the product generation.
+fn find_signing_cert(
+    cert_chain: &[CertTableEntry],
+    signing_key: SigningKey,
+) -> Result<Option<x509::X509>> {
+    match signing_key {
+        SigningKey::Vcek => cert_chain
+            .iter()
+            .find(|c| c.cert_type == CertType::VCEK)
+            .map(|c| x509::X509::from_der(c.data()).context("Failed to load VCEK"))
+            .transpose(),
+        SigningKey::Vlek => Ok(cert_chain
+            .iter()
+            .filter_map(|c| x509::X509::from_der(c.data()).ok())
+            .find(|cert| {
+                common_name(cert.issuer_name()).is_some_and(|cn| cn.starts_with("SEV-VLEK-"))
+            })),
     }
-
-    // ask, ark
-    Ok((certs[0].clone(), certs[1].clone()))
 }
 
-fn verify_cert_chain(cert_chain: &[CertTableEntry]) -> Result<x509::X509> {
-    let (ask, ark) = load_milan_cert_chain()?;
-
-    let raw_vcek = cert_chain
+/// Find the ASVK in the certificates of the evidence, which a host with a
+/// VLEK gives in place of the ASK.
+fn find_asvk(cert_chain: &[CertTableEntry]) -> Option<x509::X509> {
+    cert_chain
         .iter()
-        .find(|c| c.cert_type == CertType::VCEK)
-        .ok_or_else(|| anyhow!("VCEK not found."))?;
-    let vcek = x509::X509::from_der(raw_vcek.data()).context("Failed to load VCEK")?;
-
-    // ARK -> ARK
-    ark.verify(&(ark.public_key().unwrap() as PKey<Public>))
-        .context("Invalid ARK Signature")?;
+        .filter(|c| c.cert_type == CertType::ASK)
+        .filter_map(|c| x509::X509::from_der(c.data()).ok())
+        .find(|cert| common_name(cert.subject_name()).is_some_and(|cn| cn.starts_with("SEV-VLEK-")))
+}
 
-    // ARK -> ASK
-    ask.verify(&(ark.public_key()? as PKey<Public>))
-        .context("Invalid ASK Signature")?;
+/// Get the ASVK of the VLEK from the provider. If it cannot be resolved, the
+/// VLEK may still be issued by a trusted ASVK given in the config.
+async fn get_asvk(vlek: &x509::X509, provider: &CertificateProvider) -> Option<x509::X509> {
+    let product = vlek_product(vlek)?;
+    provider.get_asvk(&product).await.unwrap_or_else(|e| {
+        warn!("Cannot get the ASVK of {product}: {e}");
+        None
+    })
+}
 
-    // ASK -> VCEK
-    vcek.verify(&(ask.public_key()? as PKey<Public>))
-        .context("Invalid VCEK Signature")?;
+/// Whether the key of the VCEK or VLEK verifies the report signature.
+fn signs_report(vcek: &x509::X509, sig: &ecdsa::EcdsaSig, data: &[u8]) -> bool {
+    vcek.public_key()
+        .and_then(EcKey::try_from)
+        .and_then(|key| sig.verify(data, &key))
+        .unwrap_or(false)
+}
 
-    Ok(vcek)
+fn verify_cert_chain(
+    vcek: &x509::X509,
+    chains: &TrustedChains,
+    product: Option<ProcessorGeneration>,
+    asvk: Option<&x509::X509>,
+) -> Result<AmdChain> {
+    // ARK -> ASK -> VCEK, or ARK -> ASVK -> VLEK, with the ASK or ASVK and
+    // ARK of the product generation
+    chains
+        .chain_of(vcek, product, asvk)
+        .context("Invalid VCEK Signature")
 }
 
 fn calculate_expected_report_data(nonce: &String, tee_pubkey: &TeePubKey) -> [u8; 64] {
@@ -193,29 +303,16 @@ fn calculate_expected_report_data(nonce: &String, tee_pubkey: &TeePubKey) -> [u8
     hash
 }
 
-fn parse_tee_evidence(report: &AttestationReport) -> TeeEvidenceParsedClaim {
-    let claims_map = json!({
-        // policy fields
-        "policy_abi_major": format!("{}",report.policy.abi_major()),
-        "policy_abi_minor": format!("{}", report.policy.abi_minor()),
-        "policy_smt_allowed": format!("{}", report.policy.smt_allowed()),
-        "policy_migrate_ma": format!("{}", report.policy.migrate_ma_allowed()),
-        "policy_debug_allowed": format!("{}", report.policy.debug_allowed()),
-        "policy_single_socket": format!("{}", report.policy.single_socket_required()),
-
-        // versioning info
-        "reported_tcb_bootloader": format!("{}", report.reported_tcb.bootloader),
-        "reported_tcb_tee": format!("{}", report.reported_tcb.tee),
-        "reported_tcb_snp": format!("{}", report.reported_tcb.snp),
-        "reported_tcb_microcode": format!("{}", report.reported_tcb.microcode),
-
-        // platform info
-        "platform_tsme_enabled": format!("{}", report.plat_info.tsme_enabled()),
-        "platform_smt_enabled": format!("{}", report.plat_info.smt_enabled()),
-
-        // measurement
-        "measurement": format!("{}", base64::engine::general_purpose::STANDARD.encode(report.measurement)),
-    });
+fn parse_tee_evidence(
+    report: &AttestationReport,
+    revocation_status: Option<&RevocationStatus>,
+) -> TeeEvidenceParsedClaim {
+    let mut claims_map = parse_report(report);
+
+    // revocation check
+    if let Some(claims) = claims_map.as_object_mut() {
+        claims.extend(revocation_claims(revocation_status));
+    }
 
     claims_map as TeeEvidenceParsedClaim
 }
@@ -223,36 +320,8 @@ fn parse_tee_evidence(report: &AttestationReport) -> TeeEvidenceParsedClaim {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use openssl::nid::Nid;
     use sev::firmware::host::CertTableEntry;
 
-    #[test]
-    fn check_milan_certificates() {
-        let (ask, ark) = load_milan_cert_chain().unwrap();
-        assert_eq!(get_common_name(&ark).unwrap(), "ARK-Milan");
-        assert_eq!(get_common_name(&ask).unwrap(), "SEV-Milan");
-
-        assert!(ark
-            .verify(&(ark.public_key().unwrap() as PKey<Public>))
-            .context("Invalid ARK Signature")
-            .unwrap());
-
-        assert!(ask
-            .verify(&(ark.public_key().unwrap() as PKey<Public>))
-            .context("Invalid ASK Signature")
-            .unwrap());
-    }
-
-    fn get_common_name(cert: &x509::X509) -> Result<String> {
-        let mut entries = cert.subject_name().entries_by_nid(Nid::COMMONNAME);
-
-        if let Some(e) = entries.next() {
-            assert_eq!(entries.count(), 0);
-            return Ok(e.data().as_utf8()?.to_string());
-        }
-        Err(anyhow!("No CN found"))
-    }
-
     #[test]
     fn check_vcek_parsing() {
         let vcek_der = include_bytes!("test-vcek.der");
@@ -302,7 +371,18 @@ mod tests {
     fn check_vcek_signature_verification() {
         let vcek = include_bytes!("test-vcek.der").to_vec();
         let cert_table = vec![CertTableEntry::new(CertType::VCEK, vcek)];
-        verify_cert_chain(&cert_table).unwrap();
+        let vcek = find_signing_cert(&cert_table, SigningKey::Vcek)
+            .unwrap()
+            .unwrap();
+        let chains = TrustedChains::new(&SnpConfig::default()).unwrap();
+        verify_cert_chain(&vcek, &chains, None, None).unwrap();
+        verify_cert_chain(&vcek, &chains, Some(ProcessorGeneration::Milan), None).unwrap();
+        assert!(verify_cert_chain(&vcek, &chains, Some(ProcessorGeneration::Genoa), None).is_err());
+
+        // the VCEK is not issued by an ASVK
+        assert!(find_signing_cert(&cert_table, SigningKey::Vlek)
+            .unwrap()
+            .is_none());
     }
 
     #[test]
@@ -313,6 +393,56 @@ mod tests {
         vcek[7] += 1;
 
         let cert_table = vec![CertTableEntry::new(CertType::VCEK, vcek)];
-        assert!(verify_cert_chain(&cert_table).is_err());
+        let chains = TrustedChains::new(&SnpConfig::default()).unwrap();
+        let verified = find_signing_cert(&cert_table, SigningKey::Vcek)
+            .and_then(|vcek| verify_cert_chain(&vcek.unwrap(), &chains, None, None));
+        assert!(verified.is_err());
+    }
+
+    #[test]
+    fn check_vlek_signature_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chain.pem");
+        std::fs::write(&path, include_bytes!("../amd/test-lab-ask-ark.pem")).unwrap();
+        let config = SnpConfig {
+            trusted_chains: vec![path],
+            ..Default::default()
+        };
+        let chains = TrustedChains::new(&config).unwrap();
+
+        let vlek = include_bytes!("test-vlek.der").to_vec();
+        let asvk = x509::X509::from_pem(include_bytes!("../amd/test-lab-asvk.pem"))
+            .unwrap()
+            .to_der()
+            .unwrap();
+        let cert_table = vec![
+            CertTableEntry::new(CertType::ASK, asvk),
+            CertTableEntry::new(CertType::OTHER(uuid::Uuid::new_v4()), vlek),
+        ];
+        let vlek = find_signing_cert(&cert_table, SigningKey::Vlek)
+            .unwrap()
+            .unwrap();
+        let asvk = find_asvk(&cert_table).unwrap();
+        let chain = verify_cert_chain(&vlek, &chains, None, Some(&asvk)).unwrap();
+        assert_eq!(chain.product_name().unwrap(), "Lab");
+
+        // the VLEK is not issued by an ASK
+        assert!(verify_cert_chain(&vlek, &chains, None, None).is_err());
+        assert!(find_signing_cert(&cert_table, SigningKey::Vcek)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn check_signing_key() {
+        let mut report = vec![0u8; 0x4a0];
+        assert_eq!(signing_key(&report).unwrap(), SigningKey::Vcek);
+
+        report[SIGNING_KEY_OFFSET] = 1 << 2;
+        assert_eq!(signing_key(&report).unwrap(), SigningKey::Vlek);
+
+        report[SIGNING_KEY_OFFSET] = 7 << 2;
+        assert!(signing_key(&report).is_err());
+        assert!(signing_key(&[]).is_err());
     }
 }
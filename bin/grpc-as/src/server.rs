@@ -1,15 +1,18 @@
 use anyhow::{anyhow, Result};
-use attestation_service::{config::Config, AttestationService as Service, Tee};
+use attestation_service::{config::Config, AttestationService as Service, EvidenceDenied, Tee};
 use log::{debug, info};
 use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
 use crate::as_api::attestation_service_server::{AttestationService, AttestationServiceServer};
 use crate::as_api::{
-    AttestationRequest, AttestationResponse, SetPolicyRequest, SetPolicyResponse, Tee as GrpcTee,
+    AttestationRequest, AttestationResponse, ChallengeRequest, ChallengeResponse,
+    DeletePolicyRequest, DeletePolicyResponse, GetPolicyHistoryRequest, GetPolicyHistoryResponse,
+    GetPolicyRequest, GetPolicyResponse, ListPoliciesRequest, ListPoliciesResponse,
+    RollbackPolicyRequest, RollbackPolicyResponse, SetPolicyRequest, SetPolicyResponse,
+    Tee as GrpcTee, VerifyTokenRequest, VerifyTokenResponse,
 };
 
 use crate::rvps_api::reference_value_provider_service_server::{
@@ -18,11 +21,14 @@ use crate::rvps_api::reference_value_provider_service_server::{
 
 use crate::rvps_api::{
     ReferenceValueQueryRequest, ReferenceValueQueryResponse, ReferenceValueRegisterRequest,
-    ReferenceValueRegisterResponse,
+    ReferenceValueRegisterResponse, ReferenceValuesQueryRequest, ReferenceValuesQueryResponse,
 };
 
 const DEFAULT_SOCK: &str = "127.0.0.1:3000";
 
+/// The metadata key of the attestation token returned with a denial.
+const DENIAL_TOKEN_KEY: &str = "attestation-token";
+
 fn to_kbs_tee(tee: GrpcTee) -> Tee {
     match tee {
         GrpcTee::Sev => Tee::Sev,
@@ -64,7 +70,7 @@ impl AttestationServer {
 }
 
 #[tonic::async_trait]
-impl AttestationService for Arc<RwLock<AttestationServer>> {
+impl AttestationService for Arc<AttestationServer> {
     async fn set_attestation_policy(
         &self,
         request: Request<SetPolicyRequest>,
@@ -76,14 +82,94 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
         let set_policy_input: as_types::SetPolicyInput = serde_json::from_str(&request.input)
             .map_err(|_| Status::aborted("Bad SetPolicyInput"))?;
 
-        self.write()
-            .await
+        let decision = self
             .attestation_service
             .set_policy(set_policy_input)
             .await
             .map_err(|e| Status::aborted(format!("Set Attestation Policy Failed: {e}")))?;
+        let dry_run_decision = match decision {
+            Some(decision) => serde_json::to_string(&decision)
+                .map_err(|e| Status::internal(format!("Serialize policy decision failed: {e}")))?,
+            None => String::new(),
+        };
+
+        Ok(Response::new(SetPolicyResponse { dry_run_decision }))
+    }
+
+    async fn list_attestation_policies(
+        &self,
+        _request: Request<ListPoliciesRequest>,
+    ) -> Result<Response<ListPoliciesResponse>, Status> {
+        let policy_ids = self
+            .attestation_service
+            .list_policies()
+            .await
+            .map_err(|e| Status::aborted(format!("List Attestation Policies Failed: {e}")))?;
+
+        Ok(Response::new(ListPoliciesResponse { policy_ids }))
+    }
+
+    async fn get_attestation_policy(
+        &self,
+        request: Request<GetPolicyRequest>,
+    ) -> Result<Response<GetPolicyResponse>, Status> {
+        let request: GetPolicyRequest = request.into_inner();
+
+        let policy = self
+            .attestation_service
+            .get_policy(
+                &request.policy_id,
+                Some(request.version).filter(|version| *version != 0),
+            )
+            .await
+            .map_err(|e| Status::not_found(format!("Get Attestation Policy Failed: {e}")))?;
+
+        Ok(Response::new(GetPolicyResponse { policy }))
+    }
+
+    async fn delete_attestation_policy(
+        &self,
+        request: Request<DeletePolicyRequest>,
+    ) -> Result<Response<DeletePolicyResponse>, Status> {
+        let request: DeletePolicyRequest = request.into_inner();
+
+        self.attestation_service
+            .delete_policy(&request.policy_id)
+            .await
+            .map_err(|e| Status::aborted(format!("Delete Attestation Policy Failed: {e}")))?;
+
+        Ok(Response::new(DeletePolicyResponse {}))
+    }
+
+    async fn get_attestation_policy_history(
+        &self,
+        request: Request<GetPolicyHistoryRequest>,
+    ) -> Result<Response<GetPolicyHistoryResponse>, Status> {
+        let request: GetPolicyHistoryRequest = request.into_inner();
+
+        let history = self
+            .attestation_service
+            .policy_history(&request.policy_id)
+            .await
+            .map_err(|e| Status::aborted(format!("Get Attestation Policy History Failed: {e}")))?;
+
+        let history = serde_json::to_string(&history)
+            .map_err(|e| Status::internal(format!("Serialize policy history: {e}")))?;
+        Ok(Response::new(GetPolicyHistoryResponse { history }))
+    }
+
+    async fn rollback_attestation_policy(
+        &self,
+        request: Request<RollbackPolicyRequest>,
+    ) -> Result<Response<RollbackPolicyResponse>, Status> {
+        let request: RollbackPolicyRequest = request.into_inner();
+
+        self.attestation_service
+            .rollback_policy(&request.policy_id, request.version)
+            .await
+            .map_err(|e| Status::aborted(format!("Rollback Attestation Policy Failed: {e}")))?;
 
-        Ok(Response::new(SetPolicyResponse {}))
+        Ok(Response::new(RollbackPolicyResponse {}))
     }
 
     async fn attestation_evaluate(
@@ -95,8 +181,6 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
         debug!("Evidence: {}", &request.evidence);
 
         let attestation_token = self
-            .read()
-            .await
             .attestation_service
             .evaluate(
                 to_kbs_tee(
@@ -105,19 +189,67 @@ impl AttestationService for Arc<RwLock<AttestationServer>> {
                 ),
                 &request.nonce,
                 &request.evidence,
+                request.policy_ids.clone(),
+                Some(request.audience.as_str()).filter(|aud| !aud.is_empty()),
+                Some(request.subject.as_str()).filter(|sub| !sub.is_empty()),
             )
             .await
-            .map_err(|e| Status::aborted(format!("Attestation: {e}")))?;
+            .map_err(|e| match e.downcast_ref::<EvidenceDenied>() {
+                Some(denied) => {
+                    let mut status = Status::permission_denied(format!("Attestation: {denied}"));
+                    if let Ok(token) = denied.token.parse() {
+                        status.metadata_mut().insert(DENIAL_TOKEN_KEY, token);
+                    }
+                    status
+                }
+                None => Status::aborted(format!("Attestation: {e}")),
+            })?;
 
         debug!("Attestation Token: {}", &attestation_token);
 
         let res = AttestationResponse { attestation_token };
         Ok(Response::new(res))
     }
+
+    async fn get_attestation_challenge(
+        &self,
+        _request: Request<ChallengeRequest>,
+    ) -> Result<Response<ChallengeResponse>, Status> {
+        let nonce = self
+            .attestation_service
+            .issue_nonce()
+            .map_err(|e| Status::failed_precondition(format!("Attestation Challenge: {e}")))?;
+
+        let res = ChallengeResponse {
+            nonce: nonce.value,
+            expires_at: nonce.expires_at.unix_timestamp(),
+        };
+        Ok(Response::new(res))
+    }
+
+    async fn verify_attestation_token(
+        &self,
+        request: Request<VerifyTokenRequest>,
+    ) -> Result<Response<VerifyTokenResponse>, Status> {
+        let request: VerifyTokenRequest = request.into_inner();
+
+        let claims = self
+            .attestation_service
+            .verify_token(
+                &request.token,
+                Some(request.audience.as_str()).filter(|aud| !aud.is_empty()),
+            )
+            .map_err(|e| Status::unauthenticated(format!("Verify token: {e}")))?;
+
+        let res = VerifyTokenResponse {
+            claims: claims.to_string(),
+        };
+        Ok(Response::new(res))
+    }
 }
 
 #[tonic::async_trait]
-impl ReferenceValueProviderService for Arc<RwLock<AttestationServer>> {
+impl ReferenceValueProviderService for Arc<AttestationServer> {
     async fn query_reference_value(
         &self,
         _request: Request<ReferenceValueQueryRequest>,
@@ -128,6 +260,16 @@ impl ReferenceValueProviderService for Arc<RwLock<AttestationServer>> {
         Err(status)
     }
 
+    async fn query_reference_values(
+        &self,
+        _request: Request<ReferenceValuesQueryRequest>,
+    ) -> Result<Response<ReferenceValuesQueryResponse>, Status> {
+        let status =
+            Status::aborted("Cannot query reference values using RVPS as a submodule in AS.");
+
+        Err(status)
+    }
+
     async fn register_reference_value(
         &self,
         request: Request<ReferenceValueRegisterRequest>,
@@ -138,9 +280,7 @@ impl ReferenceValueProviderService for Arc<RwLock<AttestationServer>> {
 
         let message = serde_json::from_str(&request.message)
             .map_err(|e| Status::aborted(format!("Parse message: {e}")))?;
-        self.write()
-            .await
-            .attestation_service
+        self.attestation_service
             .registry_reference_value(message)
             .await
             .map_err(|e| Status::aborted(format!("Register reference value: {e}")))?;
@@ -158,9 +298,7 @@ pub async fn start(
     let socket = socket.unwrap_or(DEFAULT_SOCK).parse()?;
     info!("Listen socket: {}", &socket);
 
-    let attestation_server = Arc::new(RwLock::new(
-        AttestationServer::new(rvps_addr, config_path).await?,
-    ));
+    let attestation_server = Arc::new(AttestationServer::new(rvps_addr, config_path).await?);
 
     Server::builder()
         .add_service(AttestationServiceServer::new(attestation_server.clone()))
@@ -3,7 +3,6 @@ use attestation_service::rvps::{Core, Store, RVPSAPI};
 use log::{debug, info};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
@@ -12,15 +11,15 @@ use crate::rvps_api::reference_value_provider_service_server::{
 };
 use crate::rvps_api::{
     ReferenceValueQueryRequest, ReferenceValueQueryResponse, ReferenceValueRegisterRequest,
-    ReferenceValueRegisterResponse,
+    ReferenceValueRegisterResponse, ReferenceValuesQueryRequest, ReferenceValuesQueryResponse,
 };
 
 pub struct RVPSServer {
-    rvps: Arc<Mutex<Core>>,
+    rvps: Arc<Core>,
 }
 
 impl RVPSServer {
-    pub fn new(rvps: Arc<Mutex<Core>>) -> Self {
+    pub fn new(rvps: Arc<Core>) -> Self {
         Self { rvps }
     }
 }
@@ -37,8 +36,6 @@ impl ReferenceValueProviderService for RVPSServer {
 
         let rvs = self
             .rvps
-            .lock()
-            .await
             .get_digests(&request.name)
             .await
             .map_err(|e| Status::aborted(format!("Query reference value: {e}")))?;
@@ -52,6 +49,30 @@ impl ReferenceValueProviderService for RVPSServer {
         Ok(Response::new(res))
     }
 
+    async fn query_reference_values(
+        &self,
+        request: Request<ReferenceValuesQueryRequest>,
+    ) -> Result<Response<ReferenceValuesQueryResponse>, Status> {
+        let request = request.into_inner();
+
+        info!("query {:?}", request.names);
+
+        let names: Vec<&str> = request.names.iter().map(String::as_str).collect();
+        let rvs = self
+            .rvps
+            .get_digests_batch(&names)
+            .await
+            .map_err(|e| Status::aborted(format!("Query reference values: {e}")))?;
+        let reference_value_results = serde_json::to_string(&rvs)
+            .map_err(|e| Status::aborted(format!("Serde reference values: {e}")))?;
+        info!("Reference values: {}", reference_value_results);
+
+        let res = ReferenceValuesQueryResponse {
+            reference_value_results,
+        };
+        Ok(Response::new(res))
+    }
+
     async fn register_reference_value(
         &self,
         request: Request<ReferenceValueRegisterRequest>,
@@ -63,8 +84,6 @@ impl ReferenceValueProviderService for RVPSServer {
         let message = serde_json::from_str(&request.message)
             .map_err(|e| Status::aborted(format!("Parse message: {e}")))?;
         self.rvps
-            .lock()
-            .await
             .verify_and_extract(message)
             .await
             .map_err(|e| Status::aborted(format!("Register reference value: {e}")))?;
@@ -76,8 +95,7 @@ impl ReferenceValueProviderService for RVPSServer {
 
 pub async fn start(socket: SocketAddr, storage: Box<dyn Store + Send + Sync>) -> Result<()> {
     let service = Core::new(storage);
-    let inner = Arc::new(Mutex::new(service));
-    let rvps_server = RVPSServer::new(inner.clone());
+    let rvps_server = RVPSServer::new(Arc::new(service));
 
     Server::builder()
         .add_service(ReferenceValueProviderServiceServer::new(rvps_server))